@@ -128,5 +128,8 @@
         defaultPackage = warnToUpdateNix packages.default;
         devShell = warnToUpdateNix devShells.default;
       }
-    );
+    )
+    // {
+      nixosModules.default = import ./module.nix self;
+    };
 }
@@ -0,0 +1,54 @@
+self: {
+  config,
+  lib,
+  pkgs,
+  ...
+}: let
+  cfg = config.services.nixos-metrics;
+  settingsFormat = pkgs.formats.toml {};
+in {
+  options.services.nixos-metrics = {
+    enable = lib.mkEnableOption "scraping and processing NixOS metrics on a schedule";
+
+    package = lib.mkOption {
+      type = lib.types.package;
+      default = self.packages.${pkgs.stdenv.hostPlatform.system}.default;
+      description = "The nixos-metrics package to use.";
+    };
+
+    settings = lib.mkOption {
+      type = settingsFormat.type;
+      default = {};
+      description = ''
+        Contents of nixos-metrics.toml, see the one in the repository for an
+        example. Relative paths are relative to /var/lib/nixos-metrics.
+      '';
+    };
+
+    environmentFile = lib.mkOption {
+      type = lib.types.nullOr lib.types.path;
+      default = null;
+      description = ''
+        File with environment variables, like the Netlify token, that are
+        referenced from the settings as ''${NAME}.
+      '';
+    };
+  };
+
+  config = lib.mkIf cfg.enable {
+    systemd.services.nixos-metrics = {
+      description = "NixOS metrics";
+      wantedBy = ["multi-user.target"];
+      wants = ["network-online.target"];
+      after = ["network-online.target"];
+      serviceConfig = {
+        ExecStart = "${cfg.package}/bin/nixos-metrics --config ${settingsFormat.generate "nixos-metrics.toml" cfg.settings} daemon";
+        EnvironmentFile = lib.mkIf (cfg.environmentFile != null) cfg.environmentFile;
+        DynamicUser = true;
+        StateDirectory = "nixos-metrics";
+        WorkingDirectory = "/var/lib/nixos-metrics";
+        Restart = "on-failure";
+      };
+    };
+  };
+}
@@ -0,0 +1,471 @@
+use crate::{
+    gtrends, netlify,
+    output::Outputs,
+    process::{
+        gaps::Interpolation,
+        reconcile::Reconciliation,
+        smooth::{self, Smoothing},
+        Options,
+    },
+};
+use anyhow::{anyhow, bail, Result};
+use clap::{Parser, Subcommand};
+use croner::Cron;
+use log::error;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Configuration file used by `config check` when no `--config` is passed.
+pub const DEFAULT_PATH: &str = "nixos-metrics.toml";
+
+/// Pipeline configuration, usually read from `nixos-metrics.toml`.
+///
+/// Every string may reference environment variables as `${NAME}` (or
+/// `${NAME:-default}`), which is how secrets like the Netlify token are
+/// kept out of the file. `$$` is a literal `$`. A setting referencing a
+/// variable that is not set is left out, so only the commands using it
+/// fail. Relative paths are relative to the working directory, just like
+/// the command line flags. Flags given on the command line take precedence
+/// over the configuration.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub sources: Sources,
+
+    /// Outputs of `process-all`
+    pub outputs: Outputs,
+
+    pub daemon: Daemon,
+
+    pub store: Store,
+
+    /// Settings left out as they reference environment variables that are
+    /// not set, e.g. `sources.netlify.token` -> `NETLIFY_NIXOS_AUTH_TOKEN`
+    #[serde(skip)]
+    pub unset: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Sources {
+    pub netlify: Netlify,
+    pub gtrends: Gtrends,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Netlify {
+    pub site_id: Option<String>,
+    pub token: Option<String>,
+    pub days: Option<i64>,
+
+    /// Directory where the scraped snapshots are kept
+    pub dir: Option<PathBuf>,
+
+    /// Cron expression (UTC) of when `daemon` scrapes and processes
+    pub schedule: Option<String>,
+
+    /// Smoothed lines added to graphs, e.g. `["pageviews=median:28:centred"]`
+    pub smoothing: Vec<Smoothing>,
+
+    /// How missing days are filled in, `linear` or `forward-fill`
+    pub interpolation: Option<Interpolation>,
+
+    /// What to do when snapshots disagree about a day, `fail`, `newest`,
+    /// `oldest` or `max`
+    pub reconciliation: Option<Reconciliation>,
+
+    /// File keeping what was read from `dir`, so later runs only parse new
+    /// snapshots, by every command processing the snapshots in `dir`
+    pub state: Option<PathBuf>,
+
+    pub outputs: Outputs,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Gtrends {
+    /// File where the scraped data is kept
+    pub data: Option<PathBuf>,
+
+    /// Cron expression (UTC) of when `daemon` scrapes and processes
+    pub schedule: Option<String>,
+
+    /// Smoothed lines added to graphs, e.g. `["gtrends=mean:3:centred"]`
+    pub smoothing: Vec<Smoothing>,
+
+    /// How missing months are filled in, `linear` or `forward-fill`
+    pub interpolation: Option<Interpolation>,
+
+    pub outputs: Outputs,
+}
+
+impl Netlify {
+    /// The state kept for the snapshots in `dir`, if it is the configured
+    /// one: the state only holds the snapshots of one directory.
+    pub fn state_of(&self, dir: Option<&Path>) -> Option<PathBuf> {
+        self.state
+            .clone()
+            .filter(|_| dir.is_some() && dir == self.dir.as_deref())
+    }
+
+    /// How the configured processing goes.
+    pub fn options(&self) -> Options {
+        Options {
+            reconciliation: self.reconciliation.unwrap_or_default(),
+            smoothing: self.smoothing.clone(),
+            interpolation: self.interpolation,
+        }
+    }
+}
+
+impl Gtrends {
+    /// How the configured processing goes.
+    pub fn options(&self) -> Options {
+        Options {
+            smoothing: self.smoothing.clone(),
+            interpolation: self.interpolation,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Daemon {
+    /// File where the last runs of every source are remembered
+    pub state: Option<PathBuf>,
+
+    /// Address to serve the daemon status on
+    pub listen: Option<SocketAddr>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Store {
+    /// SQLite database of `ingest` and `export`
+    pub db: Option<PathBuf>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config> {
+        let file_content = fs::read_to_string(path)
+            .map_err(|e| anyhow!("Unable to read file {}: {}", path.display(), e))?;
+        let mut value = toml::Value::Table(
+            toml::from_str(&file_content)
+                .map_err(|e| anyhow!("Unable to parse file {}: {}", path.display(), e))?,
+        );
+        let mut unset = BTreeMap::new();
+        interpolate(&mut value, "", &mut unset)
+            .map_err(|e| anyhow!("Unable to interpolate file {}: {}", path.display(), e))?;
+        let config: Config = value
+            .try_into()
+            .map_err(|e| anyhow!("Invalid configuration in {}: {}", path.display(), e))?;
+        Ok(Config { unset, ..config })
+    }
+
+    /// Reports that the setting `key` is left out, if it references an
+    /// environment variable that is not set, for commands needing it.
+    pub fn report_unset(&self, key: &str) {
+        if let Some(name) = self.unset.get(key) {
+            error!("{}: environment variable {} is not set", key, name);
+        }
+    }
+
+    /// Problems that would make a run with this configuration fail.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = vec![];
+        let netlify = &self.sources.netlify;
+        let gtrends = &self.sources.gtrends;
+
+        for (key, name) in &self.unset {
+            problems.push(format!("{}: environment variable {} is not set", key, name));
+        }
+
+        if let Some(days) = netlify.days {
+            if let Err(e) = netlify::check_days(days) {
+                problems.push(format!("sources.netlify.days: {}", e));
+            }
+        }
+        if netlify.site_id.is_some() != netlify.token.is_some() {
+            problems.push(
+                "sources.netlify: site_id and token need to be configured together".to_owned(),
+            );
+        }
+        if let Some(dir) = &netlify.dir {
+            if !dir.is_dir() {
+                problems.push(format!(
+                    "sources.netlify.dir: {} is not a directory",
+                    dir.display()
+                ));
+            }
+        }
+        if let Some(state) = &netlify.state {
+            check_parent("sources.netlify.state", state, &mut problems);
+        }
+        if let Some(data) = &gtrends.data {
+            check_parent("sources.gtrends.data", data, &mut problems);
+        }
+
+        for (name, schedule) in [
+            ("sources.netlify.schedule", &netlify.schedule),
+            ("sources.gtrends.schedule", &gtrends.schedule),
+        ] {
+            if let Some(schedule) = schedule {
+                if let Err(e) = Cron::from_str(schedule) {
+                    problems.push(format!("{}: {}", name, e));
+                }
+            }
+        }
+        if netlify.schedule.is_some() && netlify.dir.is_none() {
+            problems.push("sources.netlify: a schedule needs a dir to scrape into".to_owned());
+        }
+        if gtrends.schedule.is_some() && gtrends.data.is_none() {
+            problems
+                .push("sources.gtrends: a schedule needs a data file to scrape into".to_owned());
+        }
+        for (name, smoothing, graphs) in [
+            (
+                "sources.netlify.smoothing",
+                &netlify.smoothing,
+                &netlify::process::SMOOTHABLE[..],
+            ),
+            (
+                "sources.gtrends.smoothing",
+                &gtrends.smoothing,
+                &gtrends::process::SMOOTHABLE[..],
+            ),
+        ] {
+            if let Err(e) = smooth::check(smoothing, graphs) {
+                problems.push(format!("{}: {}", name, e));
+            }
+        }
+        if let Some(db) = &self.store.db {
+            check_parent("store.db", db, &mut problems);
+        }
+        if let Some(state) = &self.daemon.state {
+            check_parent("daemon.state", state, &mut problems);
+        }
+
+        for (name, outputs) in [
+            ("sources.netlify.outputs", &netlify.outputs),
+            ("sources.gtrends.outputs", &gtrends.outputs),
+            ("outputs", &self.outputs),
+        ] {
+            for (field, path) in outputs.paths() {
+                check_parent(&format!("{}.{}", name, field), path, &mut problems);
+            }
+        }
+
+        problems
+    }
+}
+
+fn check_parent(name: &str, path: &Path, problems: &mut Vec<String>) {
+    let parent = match path.parent() {
+        Some(parent) if parent != Path::new("") => parent,
+        _ => return,
+    };
+    if !parent.is_dir() {
+        problems.push(format!(
+            "{}: directory {} does not exist",
+            name,
+            parent.display()
+        ));
+    }
+}
+
+/// An environment variable referenced without a default that is not set.
+#[derive(Debug)]
+struct Unset(String);
+
+impl fmt::Display for Unset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "environment variable {} is not set", self.0)
+    }
+}
+
+impl std::error::Error for Unset {}
+
+/// Interpolates the strings in `value`, found at `key`. Settings referencing
+/// variables that are not set are removed and recorded in `unset`, strings
+/// in lists cannot be left out and are an error.
+fn interpolate(
+    value: &mut toml::Value,
+    key: &str,
+    unset: &mut BTreeMap<String, String>,
+) -> Result<()> {
+    match value {
+        toml::Value::String(s) => *s = interpolate_str(s)?,
+        toml::Value::Array(values) => {
+            for value in values {
+                interpolate(value, key, unset)?;
+            }
+        }
+        toml::Value::Table(table) => {
+            let mut removed = vec![];
+            for (name, value) in table.iter_mut() {
+                let key = if key.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{}.{}", key, name)
+                };
+                let toml::Value::String(s) = value else {
+                    interpolate(value, &key, unset)?;
+                    continue;
+                };
+                match interpolate_str(s) {
+                    Ok(interpolated) => *s = interpolated,
+                    Err(e) => match e.downcast::<Unset>() {
+                        Ok(Unset(variable)) => {
+                            unset.insert(key, variable);
+                            removed.push(name.clone());
+                        }
+                        Err(e) => return Err(e),
+                    },
+                }
+            }
+            for name in removed {
+                table.remove(&name);
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn interpolate_str(s: &str) -> Result<String> {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(i) = rest.find('$') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i + 1..];
+        if let Some(after) = rest.strip_prefix('$') {
+            out.push('$');
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix('{') {
+            let end = after
+                .find('}')
+                .ok_or_else(|| anyhow!("unterminated ${{ in {:?}", s))?;
+            let (name, default) = match after[..end].split_once(":-") {
+                Some((name, default)) => (name, Some(default)),
+                None => (&after[..end], None),
+            };
+            match (env::var(name), default) {
+                (Ok(value), _) => out.push_str(&value),
+                (Err(env::VarError::NotPresent), Some(default)) => out.push_str(default),
+                (Err(env::VarError::NotPresent), None) => return Err(Unset(name.to_owned()).into()),
+                (Err(e), _) => bail!("environment variable {}: {}", name, e),
+            }
+            rest = &after[end + 1..];
+        } else {
+            out.push('$');
+        }
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+#[derive(Parser, Debug, Clone)]
+#[command(version, about, author, long_about = None)]
+pub struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum Command {
+    /// Validate the configuration file
+    Check,
+}
+
+pub async fn run(args: &Cli, path: Option<&Path>) -> Result<()> {
+    let path = path.unwrap_or(Path::new(DEFAULT_PATH));
+    match args.command {
+        Command::Check => {
+            let config = Config::load(path)?;
+            let problems = config.problems();
+            for problem in &problems {
+                error!("{}", problem);
+            }
+            if !problems.is_empty() {
+                bail!("Found {} problem(s) in {}", problems.len(), path.display());
+            }
+            println!("Configuration {} is valid", path.display());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn variables_are_interpolated() {
+        env::set_var("NIXOS_METRICS_TEST_SITE", "nixos.org");
+        assert_eq!(
+            interpolate_str("https://${NIXOS_METRICS_TEST_SITE}/").unwrap(),
+            "https://nixos.org/"
+        );
+        assert_eq!(
+            interpolate_str("${NIXOS_METRICS_TEST_SITE:-example.org}").unwrap(),
+            "nixos.org"
+        );
+        assert_eq!(
+            interpolate_str("${NIXOS_METRICS_TEST_MISSING:-example.org}").unwrap(),
+            "example.org"
+        );
+        assert_eq!(
+            interpolate_str("$${HOME} costs $5").unwrap(),
+            "${HOME} costs $5"
+        );
+        assert!(interpolate_str("${NIXOS_METRICS_TEST_SITE").is_err());
+    }
+
+    #[test]
+    fn unset_variables_leave_settings_out() {
+        let mut value = toml::Value::Table(
+            toml::from_str(
+                r#"
+                [sources.netlify]
+                site_id = "nixos"
+                token = "${NIXOS_METRICS_TEST_TOKEN}"
+                "#,
+            )
+            .unwrap(),
+        );
+        let mut unset = BTreeMap::new();
+        interpolate(&mut value, "", &mut unset).unwrap();
+        assert_eq!(
+            unset,
+            BTreeMap::from([(
+                "sources.netlify.token".to_owned(),
+                "NIXOS_METRICS_TEST_TOKEN".to_owned()
+            )])
+        );
+
+        let config: Config = value.try_into().unwrap();
+        let config = Config { unset, ..config };
+        assert_eq!(config.sources.netlify.site_id.as_deref(), Some("nixos"));
+        assert_eq!(config.sources.netlify.token, None);
+        assert!(config.problems().contains(
+            &"sources.netlify.token: environment variable NIXOS_METRICS_TEST_TOKEN is not set"
+                .to_owned()
+        ));
+    }
+
+    #[test]
+    fn unset_variables_in_lists_are_an_error() {
+        let mut value = toml::Value::Table(
+            toml::from_str(r#"smoothing = ["${NIXOS_METRICS_TEST_SMOOTHING}"]"#).unwrap(),
+        );
+        assert!(interpolate(&mut value, "", &mut BTreeMap::new()).is_err());
+    }
+}
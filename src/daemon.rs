@@ -0,0 +1,294 @@
+use crate::{config::Config, gtrends, netlify, process};
+use anyhow::{anyhow, bail, Result};
+use axum::{extract::State, routing::get, Json, Router};
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use croner::Cron;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::to_string_pretty;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+const DEFAULT_STATE: &str = "nixos-metrics-state.json";
+
+#[derive(Parser, Debug, Clone, Default)]
+#[command(version, about, author, long_about = None)]
+pub struct Cli {
+    /// File where the last runs of every source are remembered [default: nixos-metrics-state.json]
+    #[clap(long, value_parser = clap::value_parser!(PathBuf))]
+    state: Option<PathBuf>,
+
+    /// Address to serve the daemon status on, e.g. 127.0.0.1:9101
+    #[clap(long)]
+    listen: Option<SocketAddr>,
+}
+
+impl Cli {
+    /// Fills every argument not given on the command line from `config`.
+    pub fn with_config(&self, config: &Config) -> Cli {
+        Cli {
+            state: self.state.clone().or(config.daemon.state.clone()),
+            listen: self.listen.or(config.daemon.listen),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Source {
+    Netlify,
+    Gtrends,
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Source::Netlify => write!(f, "netlify"),
+            Source::Gtrends => write!(f, "gtrends"),
+        }
+    }
+}
+
+struct Job {
+    source: Source,
+    schedule: Cron,
+}
+
+impl Job {
+    /// A job is due when it did not run since the last time it was scheduled,
+    /// which also catches up on runs missed while the daemon was down.
+    fn is_due(&self, last_run: Option<DateTime<Utc>>, now: &DateTime<Utc>) -> bool {
+        match self.schedule.find_previous_occurrence(now, true) {
+            Ok(scheduled) => last_run.is_none_or(|last_run| last_run < scheduled),
+            Err(_) => false,
+        }
+    }
+}
+
+/// What is remembered about a source between restarts.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+struct JobState {
+    last_run: Option<DateTime<Utc>>,
+    last_success: Option<DateTime<Utc>>,
+    last_error: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+struct JobStatus {
+    schedule: String,
+    running: bool,
+    next_run: Option<DateTime<Utc>>,
+    #[serde(flatten)]
+    state: JobState,
+}
+
+type Status = Arc<Mutex<BTreeMap<String, JobStatus>>>;
+
+fn load_state(path: &Path) -> Result<BTreeMap<String, JobState>> {
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    let file_content = fs::read_to_string(path)
+        .map_err(|e| anyhow!("Unable to read file {}: {}", path.display(), e))?;
+    serde_json::from_str(&file_content)
+        .map_err(|e| anyhow!("Unable to parse file {}: {}", path.display(), e))
+}
+
+fn save_state(path: &Path, status: &Status) -> Result<()> {
+    let state: BTreeMap<_, _> = status
+        .lock()
+        .expect("status lock poisoned")
+        .iter()
+        .map(|(name, job)| (name.clone(), job.state.clone()))
+        .collect();
+    // write next to the state and rename, so a crash never leaves half a file
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, to_string_pretty(&state)?)
+        .map_err(|e| anyhow!("Unable to write file {}: {}", tmp.display(), e))?;
+    fs::rename(&tmp, path).map_err(|e| anyhow!("Unable to write file {}: {}", path.display(), e))
+}
+
+fn update(status: &Status, source: Source, f: impl FnOnce(&mut JobStatus)) {
+    if let Some(job) = status
+        .lock()
+        .expect("status lock poisoned")
+        .get_mut(&source.to_string())
+    {
+        f(job)
+    }
+}
+
+async fn run_job(source: Source, config: &Config) -> Result<()> {
+    match source {
+        Source::Netlify => {
+            let dir = config
+                .sources
+                .netlify
+                .dir
+                .as_ref()
+                .ok_or(anyhow!("sources.netlify.dir is not configured"))?;
+            fs::create_dir_all(dir)
+                .map_err(|e| anyhow!("Unable to create directory {}: {}", dir.display(), e))?;
+            let result = netlify::scrape(&netlify::Cli::default().with_config(config)).await?;
+            if !result.is_complete() {
+                // keep partial snapshots out of the directory, processing would reject them
+                bail!("Scraping Netlify returned incomplete data");
+            }
+            let result = to_string_pretty(&result)?;
+            for name in [
+                "current".to_owned(),
+                Utc::now().format("%Y-%m-%d").to_string(),
+            ] {
+                let path = dir.join(format!("{}.json", name));
+                fs::write(&path, &result)
+                    .map_err(|e| anyhow!("Unable to write file {}: {}", path.display(), e))?;
+            }
+            netlify::process::run(&netlify::process::Cli::default().with_config(config)).await?;
+        }
+        Source::Gtrends => {
+            let path = config
+                .sources
+                .gtrends
+                .data
+                .as_ref()
+                .ok_or(anyhow!("sources.gtrends.data is not configured"))?;
+            let result = gtrends::scrape(&gtrends::Cli::default()).await?;
+            fs::write(path, to_string_pretty(&result)?)
+                .map_err(|e| anyhow!("Unable to write file {}: {}", path.display(), e))?;
+            gtrends::process::run(&gtrends::process::Cli::default().with_config(config)).await?;
+        }
+    }
+
+    if !config.outputs.paths().is_empty() {
+        process::all::run(&process::all::Cli::default().with_config(config)).await?;
+    }
+
+    Ok(())
+}
+
+async fn status_handler(State(status): State<Status>) -> Json<BTreeMap<String, JobStatus>> {
+    Json(status.lock().expect("status lock poisoned").clone())
+}
+
+async fn serve_status(listen: SocketAddr, status: Status) -> Result<()> {
+    let app = Router::new()
+        .route("/status", get(status_handler))
+        .with_state(status);
+    let listener = tokio::net::TcpListener::bind(listen)
+        .await
+        .map_err(|e| anyhow!("Unable to listen on {}: {}", listen, e))?;
+    info!("Serving status on http://{}/status", listen);
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            error!("Serving status failed: {}", e);
+        }
+    });
+    Ok(())
+}
+
+async fn schedule(jobs: Vec<Job>, config: &Config, state: &Path, status: Status) -> Result<()> {
+    loop {
+        for job in &jobs {
+            let now = Utc::now();
+            let last_run = status
+                .lock()
+                .expect("status lock poisoned")
+                .get(&job.source.to_string())
+                .and_then(|job| job.state.last_run);
+            if !job.is_due(last_run, &now) {
+                continue;
+            }
+
+            info!("Running {}", job.source);
+            update(&status, job.source, |job| {
+                job.running = true;
+                job.state.last_run = Some(now);
+            });
+            let result = run_job(job.source, config).await;
+            match &result {
+                Ok(()) => info!("Finished {}", job.source),
+                Err(e) => error!("Running {} failed: {:#}", job.source, e),
+            }
+            update(&status, job.source, |job| {
+                job.running = false;
+                match result {
+                    Ok(()) => {
+                        job.state.last_success = Some(Utc::now());
+                        job.state.last_error = None;
+                    }
+                    Err(e) => job.state.last_error = Some(format!("{:#}", e)),
+                }
+            });
+            save_state(state, &status)?;
+        }
+
+        let now = Utc::now();
+        let mut next_runs = vec![];
+        for job in &jobs {
+            let next_run = job.schedule.find_next_occurrence(&now, false).ok();
+            update(&status, job.source, |job| job.next_run = next_run);
+            next_runs.extend(next_run);
+        }
+        let next_run = next_runs
+            .into_iter()
+            .min()
+            .ok_or(anyhow!("None of the schedules will ever run again"))?;
+        info!("Sleeping until {}", next_run);
+        tokio::time::sleep((next_run - now).to_std().unwrap_or_default()).await;
+    }
+}
+
+pub async fn run(args: &Cli, config: &Config) -> Result<()> {
+    let mut jobs = vec![];
+    for (source, schedule) in [
+        (Source::Netlify, &config.sources.netlify.schedule),
+        (Source::Gtrends, &config.sources.gtrends.schedule),
+    ] {
+        if let Some(schedule) = schedule {
+            let schedule = Cron::from_str(schedule)
+                .map_err(|e| anyhow!("Invalid schedule for {}: {}", source, e))?;
+            jobs.push(Job { source, schedule });
+        }
+    }
+    if jobs.is_empty() {
+        bail!("Nothing to schedule, set sources.netlify.schedule or sources.gtrends.schedule in the config");
+    }
+
+    let state = args
+        .state
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_STATE));
+    let mut previous = load_state(&state)?;
+    let status: Status = Arc::new(Mutex::new(
+        jobs.iter()
+            .map(|job| {
+                (
+                    job.source.to_string(),
+                    JobStatus {
+                        schedule: job.schedule.pattern.to_string(),
+                        running: false,
+                        next_run: None,
+                        state: previous.remove(&job.source.to_string()).unwrap_or_default(),
+                    },
+                )
+            })
+            .collect(),
+    ));
+
+    if let Some(listen) = args.listen {
+        serve_status(listen, status.clone()).await?;
+    }
+
+    tokio::select! {
+        result = schedule(jobs, config, &state, status) => result,
+        _ = tokio::signal::ctrl_c() => {
+            info!("Interrupted, shutting down");
+            Ok(())
+        }
+    }
+}
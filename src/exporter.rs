@@ -0,0 +1,166 @@
+use crate::{
+    config::Config,
+    gtrends, netlify,
+    output::prometheus,
+    process::{merge, read_victoriametrics_paths, series::Metadatas, Options, VictoriaMetrics},
+};
+use anyhow::{anyhow, bail, Result};
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::IntoResponse,
+    routing::get,
+    Router,
+};
+use clap::Parser;
+use log::{error, info};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+#[derive(Parser, Debug, Clone)]
+#[command(version, about, author, long_about = None)]
+pub struct Cli {
+    /// Address to serve `/metrics` on [default: 127.0.0.1:9102]
+    #[clap(long)]
+    listen: Option<SocketAddr>,
+
+    /// VictoriaMetrics JSON line files, or directories of them, to serve instead of processing the sources
+    #[clap(long, value_parser = clap::value_parser!(PathBuf))]
+    victoriametrics: Vec<PathBuf>,
+
+    /// Directory where the Netlify data has been collected
+    #[clap(long, value_parser = clap::value_parser!(PathBuf))]
+    netlify_dir: Option<PathBuf>,
+
+    /// File where the Google Trends data has been collected
+    #[clap(long, value_parser = clap::value_parser!(PathBuf))]
+    gtrends_data: Option<PathBuf>,
+
+    /// Seconds for which loaded metrics are reused before loading them again
+    #[clap(long, default_value_t = 300)]
+    refresh: u64,
+
+    #[clap(skip)]
+    netlify_state: Option<PathBuf>,
+
+    #[clap(skip)]
+    netlify_options: Options,
+
+    #[clap(skip)]
+    gtrends_options: Options,
+}
+
+impl Cli {
+    /// Fills every argument not given on the command line from `config`.
+    pub fn with_config(&self, config: &Config) -> Cli {
+        let mut args = self.clone();
+        if args.victoriametrics.is_empty() {
+            args.netlify_dir = args.netlify_dir.or(config.sources.netlify.dir.clone());
+            args.gtrends_data = args.gtrends_data.or(config.sources.gtrends.data.clone());
+        }
+        args.netlify_state = config.sources.netlify.state_of(args.netlify_dir.as_deref());
+        args.netlify_options = config.sources.netlify.options();
+        args.gtrends_options = config.sources.gtrends.options();
+        args
+    }
+
+    /// Loads the metrics to serve, with the metadata of the processed ones.
+    fn load(&self) -> Result<(VictoriaMetrics, Metadatas)> {
+        let mut victoriametrics = vec![];
+        let mut metadata = Metadatas::new();
+        if !self.victoriametrics.is_empty() {
+            victoriametrics = read_victoriametrics_paths(&self.victoriametrics)?;
+        } else {
+            if let Some(dir) = &self.netlify_dir {
+                let processed = netlify::process::process_dir_cached(
+                    dir,
+                    self.netlify_state.as_deref(),
+                    false,
+                    &self.netlify_options,
+                )?;
+                victoriametrics.extend(processed.victoriametrics);
+                metadata.extend(processed.metadata);
+            }
+            if let Some(data) = &self.gtrends_data {
+                let processed = gtrends::process::process_file(data, &self.gtrends_options)?;
+                victoriametrics.extend(processed.victoriametrics);
+                metadata.extend(processed.metadata);
+            }
+        }
+        Ok((merge(victoriametrics)?, metadata))
+    }
+
+    fn render(&self) -> Result<String> {
+        let (victoriametrics, metadata) = self.load()?;
+        prometheus::render_latest(&victoriametrics, &metadata)
+    }
+}
+
+struct Cache {
+    args: Cli,
+    rendered: Option<(Instant, String)>,
+}
+
+type Shared = Arc<Mutex<Cache>>;
+
+async fn metrics_handler(State(cache): State<Shared>) -> impl IntoResponse {
+    let mut cache = cache.lock().await;
+    let refresh = Duration::from_secs(cache.args.refresh);
+    let fresh = matches!(&cache.rendered, Some((loaded, _)) if loaded.elapsed() < refresh);
+
+    if !fresh {
+        let args = cache.args.clone();
+        let result = tokio::task::spawn_blocking(move || args.render())
+            .await
+            .map_err(|e| anyhow!(e))
+            .and_then(|result| result);
+        match result {
+            Ok(rendered) => cache.rendered = Some((Instant::now(), rendered)),
+            // keep serving what we had, Prometheus marks the target as down otherwise
+            Err(e) => error!("Loading metrics failed: {:#}", e),
+        }
+    }
+
+    match &cache.rendered {
+        Some((_, rendered)) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, CONTENT_TYPE)],
+            rendered.clone(),
+        ),
+        None => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            [(header::CONTENT_TYPE, CONTENT_TYPE)],
+            "Loading metrics failed, see the logs\n".to_owned(),
+        ),
+    }
+}
+
+pub async fn run(args: &Cli) -> Result<()> {
+    if args.victoriametrics.is_empty() && args.netlify_dir.is_none() && args.gtrends_data.is_none()
+    {
+        bail!("Nothing to serve, pass --victoriametrics, --netlify-dir or --gtrends-data");
+    }
+    // fail early instead of on the first scrape
+    let rendered = args.render()?;
+
+    let listen = args
+        .listen
+        .unwrap_or_else(|| SocketAddr::from(([127, 0, 0, 1], 9102)));
+    let app = Router::new()
+        .route("/metrics", get(metrics_handler))
+        .with_state(Arc::new(Mutex::new(Cache {
+            args: args.clone(),
+            rendered: Some((Instant::now(), rendered)),
+        })));
+    let listener = tokio::net::TcpListener::bind(listen)
+        .await
+        .map_err(|e| anyhow!("Unable to listen on {}: {}", listen, e))?;
+    info!("Serving metrics on http://{}/metrics", listen);
+    axum::serve(listener, app).await?;
+    Ok(())
+}
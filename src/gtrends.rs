@@ -9,7 +9,7 @@ pub mod process;
 
 const KEYWORDS: [&str; 3] = ["NixOS", "nix-shell", "nixpkgs"];
 
-#[derive(Parser, Debug, Clone)]
+#[derive(Parser, Debug, Clone, Default)]
 #[command(version, about, author, long_about = None)]
 pub struct Cli {}
 
@@ -39,12 +39,21 @@ struct GtrendsResult {
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
-struct GtrendsData {
+pub struct GtrendsData {
     query: Vec<String>,
     result: GtrendsResult,
 }
 
-pub async fn run(_args: &Cli) -> Result<()> {
+pub async fn run(args: &Cli) -> Result<()> {
+    let output = scrape(args).await?;
+
+    println!("{}", to_string_pretty(&output)?);
+
+    Ok(())
+}
+
+/// Fetches the search interest of all keywords from Google Trends.
+pub async fn scrape(_args: &Cli) -> Result<GtrendsData> {
     let result = tokio::task::spawn_blocking(move || -> Result<_> {
         let keywords = Keywords::new(KEYWORDS.to_vec());
         let country = Country::ALL;
@@ -61,12 +70,8 @@ pub async fn run(_args: &Cli) -> Result<()> {
     })
     .await??;
 
-    let output = GtrendsData {
+    Ok(GtrendsData {
         query: KEYWORDS.map(|x| x.to_string()).to_vec(),
         result,
-    };
-
-    println!("{}", to_string_pretty(&output)?);
-
-    Ok(())
+    })
 }
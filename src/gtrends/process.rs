@@ -1,95 +1,214 @@
 use crate::{
+    config::Config,
     gtrends,
-    process::{Graphs, Line, VictoriaMetric, VictoriaMetrics},
+    output::Outputs,
+    process::{
+        anomaly, derive,
+        forecast::{self, Horizon, Model},
+        gaps::Interpolation,
+        growth,
+        resample::{self, Aggregation, Period},
+        series::{Metadata, Metadatas, Resolution, Series},
+        smooth::{self, Smoothing},
+        Gaps, Graphs, Labels, Options, Processed, VictoriaMetrics,
+    },
 };
 use anyhow::{anyhow, Result};
 use clap::Parser;
 use serde::{Deserialize, Serialize};
-use std::collections::{BTreeMap, HashMap};
+use std::collections::BTreeMap;
 use std::fmt::Debug;
 use std::fs;
-use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-#[derive(Parser, Debug, Clone)]
+/// How far ahead search interest is projected.
+const FORECAST: Horizon = Horizon::Months(12);
+
+/// Graphs smoothed lines can be added to.
+pub const SMOOTHABLE: [&str; 1] = ["gtrends"];
+
+#[derive(Parser, Debug, Clone, Default)]
 #[command(version, about, author, long_about = None)]
 pub struct Cli {
-    // directory where the data has been collected.
-    #[clap(long, default_value = ".", value_parser = clap::value_parser!(PathBuf))]
-    data: PathBuf,
-
+    // file where the data has been collected.
     #[clap(long, value_parser = clap::value_parser!(PathBuf))]
-    graphs_out: Option<PathBuf>,
+    data: Option<PathBuf>,
 
-    #[clap(long, value_parser = clap::value_parser!(PathBuf))]
-    victoriametrics_out: Option<PathBuf>,
+    /// Smoothed lines to add to the gtrends graph, e.g. gtrends=mean:3:centred
+    #[clap(long)]
+    smoothing: Vec<Smoothing>,
+
+    /// Fill in missing months instead of leaving them empty in graphs
+    #[clap(long, value_enum)]
+    interpolation: Option<Interpolation>,
+
+    #[command(flatten)]
+    outputs: Outputs,
+}
+
+impl Cli {
+    /// Fills every argument not given on the command line from `config`.
+    pub fn with_config(&self, config: &Config) -> Cli {
+        let gtrends = &config.sources.gtrends;
+        Cli {
+            data: self.data.clone().or(gtrends.data.clone()),
+            smoothing: if self.smoothing.is_empty() {
+                gtrends.smoothing.clone()
+            } else {
+                self.smoothing.clone()
+            },
+            interpolation: self.interpolation.or(gtrends.interpolation),
+            outputs: self.outputs.with_defaults(&gtrends.outputs),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Default)]
-struct Data {
-    gtrends: BTreeMap<String, BTreeMap<u64, f64>>,
+pub struct Data {
+    /// Search term -> search interest
+    pub gtrends: BTreeMap<String, BTreeMap<u64, f64>>,
 }
 
 pub async fn run(args: &Cli) -> Result<()> {
-    let mut data = Data::default();
-    let path = &args.data;
+    let data = args.data.as_deref().ok_or(anyhow!(
+        "No Google Trends data, pass --data or set sources.gtrends.data in the config"
+    ))?;
+    let processed = process_file(
+        data,
+        &Options {
+            smoothing: args.smoothing.clone(),
+            interpolation: args.interpolation,
+            ..Default::default()
+        },
+    )?;
+    args.outputs.write(&processed).await
+}
+
+/// What the Google Trends metrics mean, including the ones derived from them.
+pub fn metadata() -> Metadatas {
+    let mut metadata = Metadatas::from([(
+        "gtrends".to_owned(),
+        Metadata {
+            unit: "interest".to_owned(),
+            description:
+                "Google search interest per month, relative to the peak of all search terms"
+                    .to_owned(),
+            resolution: Some(Resolution::Monthly),
+        },
+    )]);
+    metadata.extend(growth::metadata(&metadata));
+    metadata.insert("anomaly".to_owned(), anomaly::metadata());
+    metadata
+}
 
-    let file_content = fs::read_to_string(&path)
+/// Processes the Google Trends data collected in `path`.
+pub fn process_file(path: &Path, options: &Options) -> Result<Processed> {
+    let file_content = fs::read_to_string(path)
         .map_err(|e| anyhow!("Unable to read file {}: {}", path.display(), e))?;
-    let json: gtrends::GtrendsData = serde_json::from_str(&file_content)
-        .map_err(|e| anyhow!("Unable to parse file {}: {}", path.display(), e))?;
+    Data::parse(path, &file_content)?.process(options)
+}
 
-    for datum in &json.result.default.timeline_data {
-        if datum.is_partial.unwrap_or(false) {
-            continue;
-        }
-        for (i, name) in json.query.iter().enumerate() {
-            if !datum.has_data[i] {
+impl Data {
+    /// Parses the content of the data read from `path`, leaving out partial
+    /// data points.
+    pub fn parse(path: &Path, file_content: &str) -> Result<Data> {
+        let mut data = Data::default();
+        let json: gtrends::GtrendsData = serde_json::from_str(file_content)
+            .map_err(|e| anyhow!("Unable to parse file {}: {}", path.display(), e))?;
+
+        for datum in &json.result.default.timeline_data {
+            if datum.is_partial.unwrap_or(false) {
                 continue;
             }
-            let time_ms = datum.time.parse::<u64>().unwrap() * 1000;
-            let value = datum.value[i] as f64;
-            data.gtrends
-                .entry(name.to_owned())
-                .or_default()
-                .entry(time_ms)
-                .or_insert(value);
+            for (i, name) in json.query.iter().enumerate() {
+                if !datum.has_data[i] {
+                    continue;
+                }
+                let time_ms = datum.time.parse::<u64>().unwrap() * 1000;
+                let value = datum.value[i] as f64;
+                data.gtrends
+                    .entry(name.to_owned())
+                    .or_default()
+                    .entry(time_ms)
+                    .or_insert(value);
+            }
         }
+        Ok(data)
     }
 
-    let graphs: Graphs = HashMap::from([(
-        "gtrends".to_owned(),
-        data.gtrends
+    /// Graphs and metrics of the data, with the smoothed lines of `options`.
+    /// Missing months are `null` unless interpolated.
+    pub fn process(self, options: &Options) -> Result<Processed> {
+        let smoothing = &options.smoothing;
+        smooth::check(smoothing, &SMOOTHABLE)?;
+
+        let metadata = metadata();
+        let gtrends_series = self
+            .gtrends
             .iter()
-            .map(|(name, gtrend)| Line::try_new(name, gtrend))
-            .collect::<Result<_>>()?,
-    )]);
+            .map(|(name, gtrend)| {
+                Series::try_new(
+                    Labels::new("gtrends")?.with("search_term", name)?,
+                    &metadata["gtrends"],
+                    gtrend,
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
 
-    let victoriametrics: VictoriaMetrics = graphs
-        .get("gtrends")
-        .expect("hard-coded hashmap access of hard-coded entry")
-        .into_iter()
-        .map(|line| VictoriaMetric::try_new("gtrends", "search_term", line))
-        .collect::<Result<_>>()?;
-
-    if let Some(graphs_out) = &args.graphs_out {
-        let mut graphs_out = fs::File::create(graphs_out)?;
-
-        writeln!(
-            &mut graphs_out,
-            "{}",
-            serde_json::to_string_pretty(&graphs)?
-        )?;
-    }
+        let mut gaps = Gaps::new();
+        let mut lines = vec![];
+        for (name, series) in self.gtrends.keys().zip(&gtrends_series) {
+            let (line, missing) = series.line(name, options.interpolation)?;
+            if !missing.is_empty() {
+                gaps.entry("gtrends".to_owned())
+                    .or_default()
+                    .insert(name.to_owned(), missing);
+            }
+            lines.push(line);
+        }
+        let mut graphs = Graphs::from([("gtrends".to_owned(), lines)]);
+
+        let victoriametrics = gtrends_series
+            .iter()
+            .map(Series::victoriametric)
+            .collect::<VictoriaMetrics>();
+
+        let series: Vec<_> = self
+            .gtrends
+            .iter()
+            .map(|(name, gtrend)| (name.clone(), gtrend))
+            .collect();
+        graphs
+            .get_mut("gtrends")
+            .expect("hard-coded hashmap access of hard-coded entry")
+            .extend(smooth::lines("gtrends", &series, smoothing, "month")?);
+        graphs
+            .get_mut("gtrends")
+            .expect("hard-coded hashmap access of hard-coded entry")
+            .extend(anomaly::lines(&series)?);
+        // monthly data is too coarse and short for a yearly season
+        graphs
+            .get_mut("gtrends")
+            .expect("hard-coded hashmap access of hard-coded entry")
+            .extend(forecast::lines(&series, Model::Holt, FORECAST)?);
+        let victoriametrics = derive(victoriametrics)?;
 
-    if let Some(victoriametrics_out) = &args.victoriametrics_out {
-        let mut victoriametrics_out = fs::File::create(victoriametrics_out)?;
-        for victoriametric in victoriametrics {
-            serde_json::to_writer(&mut victoriametrics_out, &victoriametric)?;
-            // above doesn't end in a newline
-            writeln!(&mut victoriametrics_out, "")?;
+        // search interest is relative, so periods are averaged
+        for (resolution, period) in [("quarterly", Period::Quarter), ("yearly", Period::Year)] {
+            let mut graph = vec![];
+            for (name, gtrend) in &self.gtrends {
+                graph.extend(resample::lines(name, gtrend, period, Aggregation::Mean)?);
+            }
+            graphs.insert(format!("gtrends_{}", resolution), graph);
         }
-    }
+        graphs.insert("gtrends_growth".to_owned(), growth::lines(&series)?);
 
-    Ok(())
+        Ok(Processed {
+            graphs,
+            victoriametrics,
+            gaps,
+            metadata,
+            conflicts: vec![],
+        })
+    }
 }
@@ -1,3 +1,8 @@
+pub mod config;
+pub mod daemon;
+pub mod exporter;
 pub mod gtrends;
 pub mod netlify;
+pub mod output;
 pub mod process;
+pub mod store;
@@ -1,8 +1,13 @@
 use anyhow::Result;
 use clap::{Parser, Subcommand};
 use clap_verbosity_flag::Verbosity;
-use env_logger;
-use nixos_metrics::{gtrends, netlify};
+use nixos_metrics::{
+    config::{self, Config},
+    daemon, exporter, gtrends, netlify,
+    output::{influx, remote_write},
+    process, store,
+};
+use std::path::PathBuf;
 
 #[derive(Subcommand, Debug)]
 enum Commands {
@@ -11,6 +16,28 @@ enum Commands {
     ProcessNetlify(netlify::process::Cli),
     ScrapeGtrends(gtrends::Cli),
     ProcessGtrends(gtrends::process::Cli),
+    /// Process all sources into merged graphs and a single VictoriaMetrics export
+    ProcessAll(process::all::Cli),
+    /// Render earlier VictoriaMetrics exports into the outputs, optionally with fresh data
+    Replay(process::replay::Cli),
+    /// Compare the series of two VictoriaMetrics exports
+    Diff(process::diff::Cli),
+    /// Fold the records of a VictoriaMetrics export into one per series
+    Compact(process::compact::Cli),
+    /// Inspect the pipeline configuration
+    Config(config::Cli),
+    /// Scrape and process every source on the schedules from the config
+    Daemon(daemon::Cli),
+    /// Serve the latest value of every series for Prometheus to scrape
+    ServeMetrics(exporter::Cli),
+    /// Store the collected data of every source in a SQLite database
+    Ingest(store::ingest::Cli),
+    /// Process the data stored by ingest
+    Export(store::export::Cli),
+    /// Receive Prometheus remote writes and print them, for testing the remote write output
+    ReceiveRemoteWrite(remote_write::receive::Cli),
+    /// Receive InfluxDB writes and print them, for testing the InfluxDB output
+    ReceiveInflux(influx::receive::Cli),
 }
 
 #[derive(Parser, Debug)]
@@ -19,6 +46,10 @@ struct Cli {
     #[command(flatten)]
     verbose: Verbosity,
 
+    /// Pipeline configuration, command line flags take precedence over it
+    #[arg(long, global = true, value_parser = clap::value_parser!(PathBuf))]
+    config: Option<PathBuf>,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -32,11 +63,31 @@ async fn run() -> Result<()> {
         .filter_level(cli.verbose.log_level_filter())
         .init();
 
+    let config = match (&cli.command, &cli.config) {
+        (Commands::Config(_), _) | (_, None) => Config::default(),
+        (_, Some(path)) => Config::load(path)?,
+    };
+
     match &cli.command {
-        Commands::ScrapeNetlify(cmd_args) => netlify::run(&cmd_args).await?,
-        Commands::ProcessNetlify(cmd_args) => netlify::process::run(&cmd_args).await?,
-        Commands::ScrapeGtrends(cmd_args) => gtrends::run(&cmd_args).await?,
-        Commands::ProcessGtrends(cmd_args) => gtrends::process::run(&cmd_args).await?,
+        Commands::ScrapeNetlify(cmd_args) => netlify::run(&cmd_args.with_config(&config)).await?,
+        Commands::ProcessNetlify(cmd_args) => {
+            netlify::process::run(&cmd_args.with_config(&config)).await?
+        }
+        Commands::ScrapeGtrends(cmd_args) => gtrends::run(cmd_args).await?,
+        Commands::ProcessGtrends(cmd_args) => {
+            gtrends::process::run(&cmd_args.with_config(&config)).await?
+        }
+        Commands::ProcessAll(cmd_args) => process::all::run(&cmd_args.with_config(&config)).await?,
+        Commands::Replay(cmd_args) => process::replay::run(&cmd_args.with_config(&config)).await?,
+        Commands::Diff(cmd_args) => process::diff::run(cmd_args).await?,
+        Commands::Compact(cmd_args) => process::compact::run(cmd_args).await?,
+        Commands::Config(cmd_args) => config::run(cmd_args, cli.config.as_deref()).await?,
+        Commands::Daemon(cmd_args) => daemon::run(&cmd_args.with_config(&config), &config).await?,
+        Commands::ServeMetrics(cmd_args) => exporter::run(&cmd_args.with_config(&config)).await?,
+        Commands::Ingest(cmd_args) => store::ingest::run(&cmd_args.with_config(&config)).await?,
+        Commands::Export(cmd_args) => store::export::run(&cmd_args.with_config(&config)).await?,
+        Commands::ReceiveRemoteWrite(cmd_args) => remote_write::receive::run(cmd_args).await?,
+        Commands::ReceiveInflux(cmd_args) => influx::receive::run(cmd_args).await?,
     }
 
     Ok(())
@@ -1,3 +1,4 @@
+use crate::config::Config;
 use anyhow::{anyhow, Context, Result};
 use chrono::{Duration, Local};
 use clap::Parser;
@@ -9,40 +10,63 @@ use serde::{Deserialize, Serialize};
 use serde_json::{from_str, to_string_pretty};
 use std::fmt::{Display, Formatter, Result as FmtResult};
 
+pub mod ingest;
 pub mod process;
 
+const DEFAULT_DAYS: i64 = 30;
+
 fn parse_days(src: &str) -> Result<i64> {
-    let days = src.parse()?;
+    check_days(src.parse()?)
+}
+
+pub fn check_days(days: i64) -> Result<i64> {
     if days > 30 {
         return Err(anyhow!(
             "Days can be set at maximum to 30, but are set to {}.",
-            src
+            days
         ));
     }
     if days < 1 {
         return Err(anyhow!(
             "Days can be set at minimum to 1, but are set to {}.",
-            src
+            days
         ));
     }
     Ok(days)
 }
 
-#[derive(Parser, Debug, Clone)]
+#[derive(Parser, Debug, Clone, Default)]
 #[command(version, about, author, long_about = None)]
 pub struct Cli {
     /// Netlify Site Id
     #[arg(long)]
-    site_id: String,
+    site_id: Option<String>,
 
     /// Netlify token
     #[arg(long)]
-    token: String,
+    token: Option<String>,
+
+    /// Number of days in the past from today to collect analytics data for [default: 30]
+    #[clap(long, value_parser = parse_days)]
+    days: Option<i64>,
+}
 
-    /// Number of days in the past from today to collect analytics data for
-    //#[clap(long, default_value_t = 30)]
-    #[clap(long, default_value_t = 30, value_parser = parse_days)]
-    days: i64,
+impl Cli {
+    /// Fills every argument not given on the command line from `config`.
+    pub fn with_config(&self, config: &Config) -> Cli {
+        let netlify = &config.sources.netlify;
+        if self.site_id.is_none() {
+            config.report_unset("sources.netlify.site_id");
+        }
+        if self.token.is_none() {
+            config.report_unset("sources.netlify.token");
+        }
+        Cli {
+            site_id: self.site_id.clone().or(netlify.site_id.clone()),
+            token: self.token.clone().or(netlify.token.clone()),
+            days: self.days.or(netlify.days),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -77,8 +101,16 @@ struct MetricRange {
 
 impl MetricRange {
     fn new(days: &i64) -> Self {
-        let start = Local::today().and_hms_milli(0, 0, 0, 0) - Duration::days(*days);
-        let end = Local::today().and_hms_milli(23, 59, 59, 999);
+        let today = Local::now().date_naive();
+        let start = today
+            .and_hms_milli_opt(0, 0, 0, 0)
+            .and_then(|t| t.and_local_timezone(Local).earliest())
+            .expect("start of today is a valid local time")
+            - Duration::days(*days);
+        let end = today
+            .and_hms_milli_opt(23, 59, 59, 999)
+            .and_then(|t| t.and_local_timezone(Local).latest())
+            .expect("end of today is a valid local time");
         MetricRange {
             start: start.timestamp_millis().to_string(),
             end: end.timestamp_millis().to_string(),
@@ -88,14 +120,14 @@ impl MetricRange {
 }
 
 fn get_metrics_url_for<'a>(
-    args: &'a Cli,
+    site_id: &'a str,
     range: &'a MetricRange,
     metric: &'a Metric,
 ) -> (&'a Metric, String) {
     (
         metric,
         format!("https://analytics.services.netlify.com/v2/{site_id}/{metric}?from={start}&to={end}&timezone={timezone}&resolution=day",
-            site_id = args.site_id,
+            site_id = site_id,
             metric = metric,
             start = range.start,
             end = range.end,
@@ -149,7 +181,7 @@ struct BandwidthResult {
 }
 
 #[derive(Serialize, Deserialize, Debug)]
-struct MetricsResult {
+pub struct MetricsResult {
     pageviews: Option<TupleResult>,
     visitors: Option<TupleResult>,
     pages: Option<PathResult>,
@@ -169,6 +201,10 @@ impl MetricsResult {
             sources: None,
         }
     }
+    /// Whether everything processing relies on was fetched.
+    pub fn is_complete(&self) -> bool {
+        self.pageviews.is_some() && self.visitors.is_some() && self.sources.is_some()
+    }
     fn update(&mut self, metric: &Metric, text: String) {
         match metric {
             Metric::Pageviews => self.pageviews = from_str(&text).ok(),
@@ -182,16 +218,33 @@ impl MetricsResult {
 }
 
 pub async fn run(args: &Cli) -> Result<()> {
-    let range = MetricRange::new(&args.days);
+    let result = scrape(args).await?;
+
+    println!("{}", to_string_pretty(&result)?);
+
+    Ok(())
+}
+
+/// Fetches all metrics from the Netlify analytics API.
+pub async fn scrape(args: &Cli) -> Result<MetricsResult> {
+    let site_id = args.site_id.as_deref().ok_or(anyhow!(
+        "No Netlify site id, pass --site-id or set sources.netlify.site_id in the config"
+    ))?;
+    let token = args.token.as_deref().ok_or(anyhow!(
+        "No Netlify token, pass --token or set sources.netlify.token in the config"
+    ))?;
+    let days = check_days(args.days.unwrap_or(DEFAULT_DAYS))?;
+
+    let range = MetricRange::new(&days);
     info!("MetricRange: {:?}", range);
 
     let urls = vec![
-        get_metrics_url_for(&args, &range, &Metric::Pageviews),
-        get_metrics_url_for(&args, &range, &Metric::Visitors),
-        get_metrics_url_for(&args, &range, &Metric::Pages),
-        get_metrics_url_for(&args, &range, &Metric::Bandwidth),
-        get_metrics_url_for(&args, &range, &Metric::NotFound),
-        get_metrics_url_for(&args, &range, &Metric::Sources),
+        get_metrics_url_for(site_id, &range, &Metric::Pageviews),
+        get_metrics_url_for(site_id, &range, &Metric::Visitors),
+        get_metrics_url_for(site_id, &range, &Metric::Pages),
+        get_metrics_url_for(site_id, &range, &Metric::Bandwidth),
+        get_metrics_url_for(site_id, &range, &Metric::NotFound),
+        get_metrics_url_for(site_id, &range, &Metric::Sources),
     ];
 
     info!("Started to fetch metrics");
@@ -199,8 +252,7 @@ pub async fn run(args: &Cli) -> Result<()> {
     let metrics = stream_iter(urls)
         .map(|(metric, url)| {
             let client = &client;
-            let token = args.token.clone();
-            async move { (metric, get_metrics(client, &token, &url).await) }
+            async move { (metric, get_metrics(client, token, &url).await) }
         })
         .buffer_unordered(100);
     info!("Metrics fetched!");
@@ -218,7 +270,5 @@ pub async fn run(args: &Cli) -> Result<()> {
         )
         .await;
 
-    println!("{}", to_string_pretty(&result)?);
-
-    Ok(())
+    Ok(result)
 }
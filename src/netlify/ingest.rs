@@ -0,0 +1,169 @@
+use crate::netlify::process::Snapshot;
+use anyhow::{anyhow, Result};
+use flate2::read::MultiGzDecoder;
+use rayon::prelude::*;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// How a snapshot or archive is compressed, known by its extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Compression of the file `name`, and its name without the extension of
+    /// the compression.
+    fn of(name: &str) -> (Compression, &str) {
+        if let Some(stem) = name.strip_suffix(".gz") {
+            (Compression::Gzip, stem)
+        } else if let Some(stem) = name.strip_suffix(".tgz") {
+            (Compression::Gzip, stem)
+        } else if let Some(stem) = name.strip_suffix(".zst") {
+            (Compression::Zstd, stem)
+        } else {
+            (Compression::None, name)
+        }
+    }
+
+    fn decode<'a>(self, reader: impl Read + 'a) -> Result<Box<dyn Read + 'a>> {
+        Ok(match self {
+            Compression::None => Box::new(reader),
+            Compression::Gzip => Box::new(MultiGzDecoder::new(reader)),
+            Compression::Zstd => Box::new(zstd::Decoder::new(reader)?),
+        })
+    }
+}
+
+/// Whether the file `name` is a tar archive, compressed or not.
+fn is_archive(name: &str) -> bool {
+    Compression::of(name).1.ends_with(".tar") || name.ends_with(".tgz")
+}
+
+fn name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// SHA-256 of `content` as hex, to tell whether a file changed without
+/// keeping its content around.
+pub fn hash(content: &[u8]) -> String {
+    Sha256::digest(content)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Files collected in `dir`, sorted so they are read the same on every run.
+pub fn paths(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut paths = fs::read_dir(dir)
+        .map_err(|e| anyhow!("Error listing directory {}: {}", dir.display(), e))?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<Result<Vec<_>>>()
+        .map_err(|e| anyhow!("Error listing directory {}: {}", dir.display(), e))?;
+    paths.sort();
+    Ok(paths)
+}
+
+/// Content of the snapshots in the files `paths`, each a snapshot or a tar
+/// archive of them, e.g. of a whole year, optionally compressed with gzip
+/// (`.gz`, `.tgz`) or zstd (`.zst`). Content is as decompressed, and
+/// snapshots in an archive are known by the path of the archive joined with
+/// theirs.
+///
+/// Files are read in parallel. Contents are returned sorted by path,
+/// whatever order they were read in.
+pub fn contents(paths: &[PathBuf]) -> Result<Vec<(PathBuf, Vec<u8>)>> {
+    let mut contents = paths
+        .par_iter()
+        .map(|path| {
+            if is_archive(&name(path)) {
+                read_archive(path)
+            } else {
+                Ok(vec![(path.clone(), read_file(path)?)])
+            }
+        })
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+    contents.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(contents)
+}
+
+/// Snapshots in the files `paths`, as by [`contents`], parsed in parallel.
+/// They are returned sorted by path, whatever order they were parsed in, so
+/// adding them gives the same data on every run.
+pub fn read(paths: &[PathBuf]) -> Result<Vec<(PathBuf, Snapshot)>> {
+    contents(paths)?
+        .into_par_iter()
+        .map(|(path, content)| {
+            let snapshot = Snapshot::read(&path, content.as_slice())?;
+            Ok((path, snapshot))
+        })
+        .collect()
+}
+
+/// Decompresses `content` of the file `path`, compressed as its name says.
+fn decompress(path: &Path, content: impl Read) -> Result<Vec<u8>> {
+    let (compression, _) = Compression::of(&name(path));
+    let mut decompressed = vec![];
+    compression
+        .decode(content)
+        .and_then(|mut reader| Ok(reader.read_to_end(&mut decompressed)?))
+        .map_err(|e| anyhow!("Unable to read file {}: {}", path.display(), e))?;
+    Ok(decompressed)
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>> {
+    let file =
+        File::open(path).map_err(|e| anyhow!("Unable to read file {}: {}", path.display(), e))?;
+    decompress(path, file)
+}
+
+fn read_archive(path: &Path) -> Result<Vec<(PathBuf, Vec<u8>)>> {
+    let file =
+        File::open(path).map_err(|e| anyhow!("Unable to read file {}: {}", path.display(), e))?;
+    let (compression, _) = Compression::of(&name(path));
+    let reader = compression
+        .decode(file)
+        .map_err(|e| anyhow!("Unable to read file {}: {}", path.display(), e))?;
+
+    // an archive is a single stream, so its snapshots are taken out one
+    // after the other and only decompressed in parallel
+    let mut members = vec![];
+    let mut archive = tar::Archive::new(reader);
+    let entries = archive
+        .entries()
+        .map_err(|e| anyhow!("Unable to read archive {}: {}", path.display(), e))?;
+    for entry in entries {
+        let mut entry =
+            entry.map_err(|e| anyhow!("Unable to read archive {}: {}", path.display(), e))?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let member = path.join(
+            entry
+                .path()
+                .map_err(|e| anyhow!("Unable to read archive {}: {}", path.display(), e))?,
+        );
+        let mut content = vec![];
+        entry
+            .read_to_end(&mut content)
+            .map_err(|e| anyhow!("Unable to read file {}: {}", member.display(), e))?;
+        members.push((member, content));
+    }
+
+    members
+        .into_par_iter()
+        .map(|(member, content)| {
+            let content = decompress(&member, content.as_slice())?;
+            Ok((member, content))
+        })
+        .collect()
+}
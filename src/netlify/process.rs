@@ -1,223 +1,656 @@
 use crate::{
-    netlify,
-    process::{Graphs, Line, VictoriaMetric, VictoriaMetrics},
+    config::Config,
+    netlify::{self, ingest},
+    output::Outputs,
+    process::{
+        anomaly, derive,
+        forecast::{self, Horizon, Model},
+        gaps::Interpolation,
+        growth,
+        reconcile::{Reconciler, Reconciliation, Report},
+        resample::{self, Aggregation, Period},
+        series::{Metadata, Metadatas, Resolution, Series},
+        smooth::{self, Smoothing},
+        Gaps, Graphs, Labels, Options, Processed, VictoriaMetrics,
+    },
 };
 use anyhow::{anyhow, bail, Result};
-use chrono::{prelude::DateTime, Utc};
 use clap::Parser;
+use log::{info, warn};
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::collections::{BTreeMap, HashMap};
+use std::collections::BTreeMap;
 use std::fmt::Debug;
 use std::fs;
-use std::io::Write;
-use std::path::PathBuf;
-use std::time::{Duration, UNIX_EPOCH};
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-const MS_PER_DAY: u64 = 1000 * 60 * 60 * 24;
+/// How far ahead pageviews and visitors are projected, whole weeks.
+const FORECAST: Horizon = Horizon::Days(28);
 
-#[derive(Parser, Debug, Clone)]
+/// Graphs smoothed lines can be added to.
+pub const SMOOTHABLE: [&str; 3] = ["pageviews", "visitors", "sources"];
+
+/// Smoothings used when none are configured.
+const DEFAULT_SMOOTHING: [&str; 2] = ["pageviews=mean:7", "visitors=mean:7"];
+
+#[derive(Parser, Debug, Clone, Default)]
 #[command(version, about, author, long_about = None)]
 pub struct Cli {
-    // directory where the data has been collected.
-    #[clap(long, default_value = ".", value_parser = clap::value_parser!(PathBuf))]
-    dir: PathBuf,
-
+    // directory where the data has been collected, defaults to the current directory.
+    // snapshots may be gzip or zstd compressed, or in tar archives e.g. of a year.
     #[clap(long, value_parser = clap::value_parser!(PathBuf))]
-    graphs_out: Option<PathBuf>,
+    dir: Option<PathBuf>,
+
+    /// Smoothed lines to add to the pageviews, visitors or sources graph, e.g.
+    /// pageviews=median:28:centred [default: pageviews=mean:7 visitors=mean:7]
+    #[clap(long)]
+    smoothing: Vec<Smoothing>,
 
+    /// Fill in missing days instead of leaving them empty in graphs
+    #[clap(long, value_enum)]
+    interpolation: Option<Interpolation>,
+
+    /// What to do when snapshots disagree about a day's pageviews or visitors [default: fail]
+    #[clap(long, value_enum)]
+    reconciliation: Option<Reconciliation>,
+
+    /// Keep what was read from the directory in this file, so later runs only parse new snapshots
     #[clap(long, value_parser = clap::value_parser!(PathBuf))]
-    victoriametrics_out: Option<PathBuf>,
+    state: Option<PathBuf>,
+
+    /// Read every snapshot again instead of starting from the state
+    #[clap(long)]
+    full: bool,
+
+    #[command(flatten)]
+    outputs: Outputs,
 }
 
-#[derive(Serialize, Deserialize, Debug, Default)]
-struct Data {
-    pageviews: BTreeMap<u64, u64>,
-    pageviews_7day: BTreeMap<u64, f64>,
-    visitors: BTreeMap<u64, u64>,
-    visitors_7day: BTreeMap<u64, f64>,
-    sources: HashMap<String, BTreeMap<u64, u64>>,
+impl Cli {
+    /// Fills every argument not given on the command line from `config`.
+    pub fn with_config(&self, config: &Config) -> Cli {
+        let netlify = &config.sources.netlify;
+        let dir = self.dir.clone().or(netlify.dir.clone());
+        Cli {
+            state: self.state.clone().or(netlify.state_of(dir.as_deref())),
+            dir,
+            smoothing: if self.smoothing.is_empty() {
+                netlify.smoothing.clone()
+            } else {
+                self.smoothing.clone()
+            },
+            interpolation: self.interpolation.or(netlify.interpolation),
+            reconciliation: self.reconciliation.or(netlify.reconciliation),
+            full: self.full,
+            outputs: self.outputs.with_defaults(&netlify.outputs),
+        }
+    }
 }
 
-pub async fn run(args: &Cli) -> Result<()> {
-    let mut data = Data::default();
-
-    for path in fs::read_dir(&args.dir)
-        .map_err(|e| anyhow!("Error listing directory {}: {}", args.dir.display(), e))?
-    {
-        let path = path
-            .map_err(|e| anyhow!("Error listing directory {}: {}", args.dir.display(), e))?
-            .path();
-        let file_content = fs::read_to_string(&path)
-            .map_err(|e| anyhow!("Unable to read file {}: {}", path.display(), e))?;
-        let json: netlify::MetricsResult = serde_json::from_str(&file_content)
+/// What a single snapshot contributes, without its last, still incomplete day.
+#[derive(Serialize, Debug, Default)]
+pub struct Snapshot {
+    /// Day the snapshot was taken, which its sources are counted for
+    pub date: u64,
+    pub pageviews: BTreeMap<u64, u64>,
+    pub visitors: BTreeMap<u64, u64>,
+    /// Referrer -> count, the empty referrer being direct traffic
+    pub sources: BTreeMap<String, u64>,
+}
+
+impl Snapshot {
+    /// Parses the snapshot in the file `path` as it is read.
+    pub fn read(path: &Path, reader: impl Read) -> Result<Snapshot> {
+        let json: netlify::MetricsResult = serde_json::from_reader(BufReader::new(reader))
             .map_err(|e| anyhow!("Unable to parse file {}: {}", path.display(), e))?;
         let mut pviews = json
             .pageviews
             .ok_or(anyhow!("No pageviews data in {}", path.display()))?
             .data;
-        let current_date = pviews
+        let date = pviews
             .last()
             .ok_or_else(|| anyhow!("Error empty pageviews in file {}", path.display()))?
             .0;
         pviews.truncate(pviews.len() - 1);
-        for (tstamp, datum) in pviews {
-            let v = *data.pageviews.entry(tstamp).or_insert(datum);
-            if v != datum {
-                bail!(
-                    "data mistmatch on {} (pageviews): {} from before and {} found in file {}",
-                    to_date(tstamp),
-                    v,
-                    datum,
-                    path.display()
-                );
-            }
-        }
 
         let mut visitors = json
             .visitors
             .ok_or(anyhow!("No visitors data in {}", path.display()))?
             .data;
         visitors.truncate(visitors.len() - 1);
-        for (tstamp, datum) in visitors {
-            let v = *data.visitors.entry(tstamp).or_insert(datum);
-            if v != datum {
-                bail!(
-                    "data mistmatch on {} (visitors): {} from before and {} found in file {}",
-                    to_date(tstamp),
-                    v,
-                    datum,
-                    path.display()
-                );
-            }
-        }
 
         let mut sources = json
             .sources
             .ok_or(anyhow!("No sources data in {}", path.display()))?
             .data;
         sources.truncate(sources.len() - 1);
-        for source in sources {
-            data.sources
-                .entry(source.path)
-                .or_default()
-                .insert(current_date, source.count);
+
+        Ok(Snapshot {
+            date,
+            pageviews: pviews.into_iter().collect(),
+            visitors: visitors.into_iter().collect(),
+            sources: sources
+                .into_iter()
+                .map(|source| (source.path, source.count))
+                .collect(),
+        })
+    }
+}
+
+/// Sources of a snapshot, as counted for the day it was taken.
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Sources {
+    date: u64,
+    sources: BTreeMap<String, u64>,
+}
+
+/// Everything known from the snapshots added so far.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct Data {
+    pub pageviews: BTreeMap<u64, u64>,
+    pub visitors: BTreeMap<u64, u64>,
+    pub sources: BTreeMap<String, BTreeMap<u64, u64>>,
+    /// What every snapshot said about pageviews and visitors
+    reconciler: Reconciler,
+    /// Sources of every snapshot added, by path, so they can be taken back
+    /// out
+    snapshot_sources: BTreeMap<PathBuf, Sources>,
+}
+
+/// What earlier runs read from a directory of snapshots, so later ones only
+/// parse the snapshots added since.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct State {
+    /// SHA-256 of the content of every file in `data`, by path
+    pub snapshots: BTreeMap<PathBuf, String>,
+    pub data: Data,
+}
+
+impl State {
+    fn new(reconciliation: Reconciliation) -> State {
+        State {
+            snapshots: BTreeMap::new(),
+            data: Data::new(reconciliation),
+        }
+    }
+
+    /// Reads the state kept in `path`, if there is one. A state that cannot
+    /// be parsed, e.g. written by another version, is left to be rebuilt.
+    pub fn load(path: &Path) -> Result<Option<State>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let file_content = fs::read_to_string(path)
+            .map_err(|e| anyhow!("Unable to read file {}: {}", path.display(), e))?;
+        match serde_json::from_str(&file_content) {
+            Ok(state) => Ok(Some(state)),
+            Err(e) => {
+                warn!(
+                    "Unable to parse file {}, reading all snapshots again: {}",
+                    path.display(),
+                    e
+                );
+                Ok(None)
+            }
         }
     }
 
-    data.pageviews_7day = avg_7day(&data.pageviews);
-    data.visitors_7day = avg_7day(&data.visitors);
+    /// Brings the state up to date with the snapshots in `dir`. Snapshots
+    /// that changed or are gone are taken back out of the data, and the new
+    /// and changed ones are added. Returns the files read.
+    pub fn update(&mut self, dir: &Path) -> Result<Vec<PathBuf>> {
+        let hashes = ingest::paths(dir)?
+            .into_par_iter()
+            .map(|path| {
+                let file_content = fs::read(&path)
+                    .map_err(|e| anyhow!("Unable to read file {}: {}", path.display(), e))?;
+                Ok((path, ingest::hash(&file_content)))
+            })
+            .collect::<Result<BTreeMap<_, _>>>()?;
 
-    let graphs: Graphs = HashMap::from([
+        let changed: Vec<_> = self
+            .snapshots
+            .iter()
+            .filter(|(path, hash)| hashes.get(*path) != Some(*hash))
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in &changed {
+            self.data.remove(path)?;
+            self.snapshots.remove(path);
+        }
+
+        let read: Vec<_> = hashes
+            .keys()
+            .filter(|path| !self.snapshots.contains_key(*path))
+            .cloned()
+            .collect();
+        for (path, snapshot) in ingest::read(&read)? {
+            self.data.add(&path, snapshot)?;
+        }
+        self.snapshots = hashes;
+        Ok(read)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        // write next to the state and rename, so a crash never leaves half a
+        // file, through a name of this save only: the daemon and the exporter
+        // may both be saving the state
+        static SAVES: AtomicUsize = AtomicUsize::new(0);
+        let tmp = path.with_extension(format!(
+            "{}.{}.tmp",
+            process::id(),
+            SAVES.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&tmp, serde_json::to_string(self)?)
+            .map_err(|e| anyhow!("Unable to write file {}: {}", tmp.display(), e))?;
+        fs::rename(&tmp, path)
+            .map_err(|e| anyhow!("Unable to write file {}: {}", path.display(), e))
+    }
+}
+
+pub async fn run(args: &Cli) -> Result<()> {
+    let dir = args.dir.as_deref().unwrap_or(Path::new("."));
+    let options = Options {
+        reconciliation: args.reconciliation.unwrap_or_default(),
+        smoothing: args.smoothing.clone(),
+        interpolation: args.interpolation,
+    };
+    let processed = process_dir_cached(dir, args.state.as_deref(), args.full, &options)?;
+    args.outputs.write(&processed).await
+}
+
+/// What the Netlify metrics mean, including the ones derived from them.
+pub fn metadata() -> Metadatas {
+    let daily = |unit: &str, description: &str| Metadata {
+        unit: unit.to_owned(),
+        description: description.to_owned(),
+        resolution: Some(Resolution::Daily),
+    };
+    let mut metadata = Metadatas::from([
         (
-            "pageviews".to_owned(),
-            Vec::from([
-                Line::try_new("Pageviews", &data.pageviews)?,
-                Line::try_new("7 day avg", &data.pageviews_7day)?,
-            ]),
+            "netlify.pageviews".to_owned(),
+            daily("pageviews", "Pageviews per day"),
         ),
         (
-            "visitors".to_owned(),
-            Vec::from([
-                Line::try_new("Visitors", &data.visitors)?,
-                Line::try_new("7 day avg", &data.visitors_7day)?,
-            ]),
+            "netlify.visitors".to_owned(),
+            daily("visitors", "Unique visitors per day"),
         ),
         (
-            "sources".to_owned(),
-            data.sources
-                .iter()
-                .map(|(name, source)| {
-                    Line::try_new(
-                        if name == "" {
-                            "direct".to_owned()
-                        } else {
-                            name.clone()
-                        },
-                        source,
-                    )
-                })
-                .collect::<Result<_>>()?,
+            "netlify.sources".to_owned(),
+            daily(
+                "pageviews",
+                "Pageviews by referrer, as of the day of a snapshot",
+            ),
         ),
     ]);
+    metadata.extend(growth::metadata(&metadata));
+    metadata.insert("anomaly".to_owned(), anomaly::metadata());
+    metadata
+}
 
-    let mut victoriametrics: VictoriaMetrics = vec![
-        VictoriaMetric::try_new(
-            "netlify.pageviews",
-            "",
-            &graphs
-                .get("pageviews")
-                .expect("hard-coded hashmap access of hard-coded entry")[0],
-        )?,
-        VictoriaMetric::try_new(
-            "netlify.visitors",
-            "",
-            &graphs
-                .get("visitors")
-                .expect("hard-coded hashmap access of hard-coded entry")[0],
-        )?,
-    ];
-
-    for source in graphs
-        .get("sources")
-        .expect("hard-coded hashmap access of hard-coded entry")
-    {
-        victoriametrics.push(VictoriaMetric::try_new(
-            "netlify.sources",
-            "source",
-            &source,
-        )?);
-    }
-
-    if let Some(graphs_out) = &args.graphs_out {
-        let mut graphs_out = fs::File::create(graphs_out)?;
-
-        writeln!(
-            &mut graphs_out,
-            "{}",
-            serde_json::to_string_pretty(&graphs)?
-        )?;
+/// Processes all Netlify snapshots collected in `dir`, see [`Data::process`]
+/// and [`ingest::read`] for what is read.
+pub fn process_dir(dir: &Path, options: &Options) -> Result<Processed> {
+    let mut data = Data::new(options.reconciliation);
+    for (path, snapshot) in ingest::read(&ingest::paths(dir)?)? {
+        data.add(&path, snapshot)?;
     }
+    data.process(options)
+}
 
-    if let Some(victoriametrics_out) = &args.victoriametrics_out {
-        let mut victoriametrics_out = fs::File::create(victoriametrics_out)?;
-        for victoriametric in victoriametrics {
-            serde_json::to_writer(&mut victoriametrics_out, &victoriametric)?;
-            // above doesn't end in a newline
-            writeln!(&mut victoriametrics_out, "")?;
-        }
+/// Processes the snapshots in `dir` with [`process_dir_incremental`] when a
+/// `state` is kept for them, or with [`process_dir`] otherwise.
+pub fn process_dir_cached(
+    dir: &Path,
+    state: Option<&Path>,
+    full: bool,
+    options: &Options,
+) -> Result<Processed> {
+    match state {
+        Some(state) => process_dir_incremental(dir, state, full, options),
+        None => process_dir(dir, options),
     }
-
-    Ok(())
 }
 
-fn to_date(ms: u64) -> String {
-    DateTime::<Utc>::from(UNIX_EPOCH + Duration::from_millis(ms))
-        .format("%Y-%m-%d")
-        .to_string()
+/// Like [`process_dir`], but starting from what earlier runs kept in the file
+/// `state`, so only the snapshots added or changed since are parsed, see
+/// [`State::update`]. The state is then saved with them.
+///
+/// Everything is read again when `full` is set or when the reconciliation
+/// changed, as what was chosen for disagreeing snapshots cannot be told from
+/// what they said.
+pub fn process_dir_incremental(
+    dir: &Path,
+    state: &Path,
+    full: bool,
+    options: &Options,
+) -> Result<Processed> {
+    let loaded = if full { None } else { State::load(state)? };
+    let mut kept = match loaded {
+        Some(kept) if kept.data.reconciler.reconciliation() == options.reconciliation => kept,
+        Some(_) => {
+            info!(
+                "Reconciliation changed since {} was written, reading all snapshots in {} again",
+                state.display(),
+                dir.display()
+            );
+            State::new(options.reconciliation)
+        }
+        None => State::new(options.reconciliation),
+    };
+
+    let read = kept.update(dir)?;
+    info!(
+        "Read {} new or changed files of {} in {}",
+        read.len(),
+        kept.snapshots.len(),
+        dir.display()
+    );
+    kept.save(state)?;
+
+    kept.data.process(options)
 }
 
-fn avg_7day(data: &BTreeMap<u64, u64>) -> BTreeMap<u64, f64> {
-    let mut avgs = BTreeMap::<u64, f64>::default();
-    let mut days = BTreeMap::<u64, u8>::default();
+impl Data {
+    /// No data yet, with disagreeing snapshots reconciled by
+    /// `reconciliation`.
+    pub fn new(reconciliation: Reconciliation) -> Data {
+        Data {
+            reconciler: Reconciler::new(reconciliation),
+            ..Default::default()
+        }
+    }
+
+    /// Adds the snapshot read from `path`. Where it disagrees with earlier
+    /// snapshots about a day's pageviews or visitors, the reconciliation
+    /// decides.
+    pub fn add(&mut self, path: &Path, snapshot: Snapshot) -> Result<()> {
+        for (metric, data) in [
+            ("netlify.pageviews", snapshot.pageviews),
+            ("netlify.visitors", snapshot.visitors),
+        ] {
+            for (tstamp, datum) in data {
+                let report = Report {
+                    file: path.to_owned(),
+                    taken: snapshot.date,
+                    value: datum as f64,
+                };
+                self.report(metric, tstamp, report)?;
+            }
+        }
+
+        let names: Vec<_> = snapshot.sources.keys().cloned().collect();
+        self.snapshot_sources.insert(
+            path.to_owned(),
+            Sources {
+                date: snapshot.date,
+                sources: snapshot.sources,
+            },
+        );
+        self.count_sources(snapshot.date, &names);
+        Ok(())
+    }
 
-    // average over the 7 days after a date
-    for (&date, &datum) in data {
-        for i in 0..7 {
-            let date = date + (i * MS_PER_DAY);
+    /// Takes back what the snapshot read from `path`, or the snapshots in
+    /// the archive `path`, contributed, as if they had never been added.
+    pub fn remove(&mut self, path: &Path) -> Result<()> {
+        for (metric, tstamp) in self.reconciler.remove(path) {
+            let value = self.reconciler.value(&metric, tstamp);
+            let values = self.values(&metric)?;
+            match value {
+                Some(value) => values.insert(tstamp, value as u64),
+                None => values.remove(&tstamp),
+            };
+        }
 
-            let avg = avgs.entry(date).or_insert(0.0);
-            let n = days.entry(date).or_insert(0);
+        let removed: Vec<_> = self
+            .snapshot_sources
+            .keys()
+            .filter(|file| file.starts_with(path))
+            .cloned()
+            .collect();
+        for file in removed {
+            if let Some(sources) = self.snapshot_sources.remove(&file) {
+                let names: Vec<_> = sources.sources.into_keys().collect();
+                self.count_sources(sources.date, &names);
+            }
+        }
+        Ok(())
+    }
 
-            *avg += datum as f64 / 7.0;
-            *n += 1
+    /// Counts the sources `names` on the day `date` again, by the last
+    /// snapshot of that day having them: snapshots of the same day replace
+    /// each other's sources in order.
+    fn count_sources(&mut self, date: u64, names: &[String]) {
+        for name in names {
+            let count = self
+                .snapshot_sources
+                .values()
+                .filter(|sources| sources.date == date)
+                .filter_map(|sources| sources.sources.get(name))
+                .next_back();
+            let counts = self.sources.entry(name.clone()).or_default();
+            match count {
+                Some(&count) => {
+                    counts.insert(date, count);
+                }
+                None => {
+                    counts.remove(&date);
+                    if counts.is_empty() {
+                        self.sources.remove(name);
+                    }
+                }
+            }
         }
     }
 
-    // only keep the ones we have a full 7 days of data for
-    for (date, n) in days {
-        if n != 7 {
-            avgs.remove(&date);
+    /// Adds what a snapshot said about the pageviews or visitors, `metric`,
+    /// of the day `tstamp`, reconciled with what earlier ones said.
+    pub fn report(&mut self, metric: &str, tstamp: u64, report: Report) -> Result<()> {
+        let value = self.reconciler.report(metric, tstamp, report)?;
+        self.values(metric)?.insert(tstamp, value as u64);
+        Ok(())
+    }
+
+    fn values(&mut self, metric: &str) -> Result<&mut BTreeMap<u64, u64>> {
+        match metric {
+            "netlify.pageviews" => Ok(&mut self.pageviews),
+            "netlify.visitors" => Ok(&mut self.visitors),
+            _ => bail!("Unknown Netlify metric {}", metric),
         }
     }
-    avgs
+
+    /// Graphs and metrics of everything added, with the smoothed lines of
+    /// `options`, or 7 day averages of pageviews and visitors if there are
+    /// none. Missing days of pageviews, visitors and sources are `null`
+    /// unless interpolated.
+    pub fn process(self, options: &Options) -> Result<Processed> {
+        let smoothing = match options.smoothing.as_slice() {
+            [] => DEFAULT_SMOOTHING
+                .iter()
+                .map(|smoothing| smoothing.parse())
+                .collect::<Result<_>>()?,
+            smoothing => smoothing.to_vec(),
+        };
+        smooth::check(&smoothing, &SMOOTHABLE)?;
+
+        let sources: Vec<_> = self
+            .sources
+            .iter()
+            .map(|(name, source)| {
+                let name = if name.is_empty() {
+                    "direct".to_owned()
+                } else {
+                    name.clone()
+                };
+                (name, source)
+            })
+            .collect();
+
+        let metadata = metadata();
+        let pageviews = Series::try_new(
+            Labels::new("netlify.pageviews")?,
+            &metadata["netlify.pageviews"],
+            &self.pageviews,
+        )?;
+        let visitors = Series::try_new(
+            Labels::new("netlify.visitors")?,
+            &metadata["netlify.visitors"],
+            &self.visitors,
+        )?;
+        let sources_series = sources
+            .iter()
+            .map(|(name, source)| {
+                Series::try_new(
+                    Labels::new("netlify.sources")?.with("source", name)?,
+                    &metadata["netlify.sources"],
+                    *source,
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut graphs = Graphs::new();
+        let mut gaps = Gaps::new();
+        for (name, label, series, data) in [
+            ("pageviews", "Pageviews", &pageviews, &self.pageviews),
+            ("visitors", "Visitors", &visitors, &self.visitors),
+        ] {
+            let (line, missing) = series.line(label, options.interpolation)?;
+            if !missing.is_empty() {
+                gaps.entry(name.to_owned())
+                    .or_default()
+                    .insert(label.to_owned(), missing);
+            }
+            let mut graph = vec![line];
+            graph.extend(smooth::lines(
+                name,
+                &[(label.to_owned(), data)],
+                &smoothing,
+                series.metadata.resolution.map_or("day", |r| r.unit()),
+            )?);
+            graph.extend(anomaly::lines(&[(label.to_owned(), data)])?);
+            graph.extend(forecast::lines(
+                &[(label.to_owned(), data)],
+                Model::HoltWinters { season: 7 },
+                FORECAST,
+            )?);
+            graphs.insert(name.to_owned(), graph);
+        }
+        let mut graph = vec![];
+        for ((name, _), series) in sources.iter().zip(&sources_series) {
+            let (line, missing) = series.line(name, options.interpolation)?;
+            if !missing.is_empty() {
+                gaps.entry("sources".to_owned())
+                    .or_default()
+                    .insert(name.to_owned(), missing);
+            }
+            graph.push(line);
+        }
+        graph.extend(smooth::lines("sources", &sources, &smoothing, "day")?);
+        graph.extend(anomaly::lines(&sources)?);
+        graphs.insert("sources".to_owned(), graph);
+
+        let all_series: Vec<_> = [pageviews, visitors]
+            .into_iter()
+            .chain(sources_series)
+            .collect();
+        let victoriametrics = all_series
+            .iter()
+            .map(Series::victoriametric)
+            .collect::<VictoriaMetrics>();
+        let victoriametrics = derive(victoriametrics)?;
+
+        for (name, label, data) in [
+            ("pageviews", "Pageviews", &self.pageviews),
+            ("visitors", "Visitors", &self.visitors),
+        ] {
+            for (resolution, period) in [("weekly", Period::Week), ("monthly", Period::Month)] {
+                graphs.insert(
+                    format!("{}_{}", name, resolution),
+                    resample::lines(label, data, period, Aggregation::Sum)?,
+                );
+            }
+            graphs.insert(
+                format!("{}_growth", name),
+                growth::lines(&[(label.to_owned(), data)])?,
+            );
+        }
+        graphs.insert("sources_growth".to_owned(), growth::lines(&sources)?);
+
+        Ok(Processed {
+            graphs,
+            victoriametrics,
+            gaps,
+            metadata,
+            conflicts: self.reconciler.conflicts(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::MS_PER_DAY;
+
+    /// Snapshot taken on `date`, with `pageviews` for the days before, and
+    /// `count` pageviews from github.com. Netlify adds the incomplete day.
+    fn snapshot(date: u64, pageviews: &[(u64, u64)], count: u64) -> String {
+        let mut days: Vec<_> = pageviews
+            .iter()
+            .map(|(day, value)| (day * MS_PER_DAY, *value))
+            .collect();
+        days.push((date * MS_PER_DAY, 1));
+        serde_json::json!({
+            "pageviews": { "data": days },
+            "visitors": { "data": days },
+            "sources": { "data": [
+                { "path": "github.com", "count": count },
+                { "path": "", "count": 1 },
+            ] },
+        })
+        .to_string()
+    }
+
+    /// Pageviews, visitors and sources of `data`, to compare with a full
+    /// read.
+    fn contents(data: &Data) -> impl PartialEq + Debug + '_ {
+        (&data.pageviews, &data.visitors, &data.sources)
+    }
+
+    #[test]
+    fn only_changed_snapshots_are_read_again() {
+        let dir =
+            std::env::temp_dir().join(format!("nixos-metrics-incremental-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, content: String| fs::write(dir.join(name), content).unwrap();
+        write("1.json", snapshot(3, &[(1, 10), (2, 20)], 5));
+        write("2.json", snapshot(4, &[(2, 20), (3, 30)], 6));
+        write("3.json", snapshot(5, &[(3, 30), (4, 40)], 7));
+
+        let mut state = State::new(Reconciliation::Newest);
+        assert_eq!(state.update(&dir).unwrap().len(), 3);
+
+        // Netlify revised a day, as a rewritten current.json would have it
+        write("2.json", snapshot(4, &[(2, 25), (3, 30)], 8));
+        assert_eq!(state.update(&dir).unwrap(), [dir.join("2.json")]);
+        assert_eq!(state.data.pageviews[&(2 * MS_PER_DAY)], 25);
+        assert_eq!(state.data.sources["github.com"][&(4 * MS_PER_DAY)], 8);
+        let mut full = State::new(Reconciliation::Newest);
+        full.update(&dir).unwrap();
+        assert_eq!(contents(&state.data), contents(&full.data));
+
+        fs::remove_file(dir.join("3.json")).unwrap();
+        assert!(state.update(&dir).unwrap().is_empty());
+        assert!(!state.data.pageviews.contains_key(&(4 * MS_PER_DAY)));
+        let mut full = State::new(Reconciliation::Newest);
+        full.update(&dir).unwrap();
+        assert_eq!(contents(&state.data), contents(&full.data));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
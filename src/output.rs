@@ -0,0 +1,395 @@
+use crate::process::{
+    merge, read_victoriametrics, to_date, Labels, Processed, VictoriaMetric, VictoriaMetrics,
+};
+use anyhow::{anyhow, bail, Result};
+use clap::Args;
+use log::{info, warn};
+use prometheus::Format;
+use reqwest::{RequestBuilder, Response, StatusCode};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub mod csv;
+pub mod graphite;
+pub mod influx;
+pub mod parquet;
+pub mod prometheus;
+pub mod remote_write;
+pub mod victoriametrics;
+
+/// Where the processed data of a source gets written to.
+#[derive(Args, Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Outputs {
+    /// Write graphs as a single JSON document
+    #[clap(long, value_parser = clap::value_parser!(PathBuf))]
+    pub graphs_out: Option<PathBuf>,
+
+    /// Write the missing days of every graph line as a JSON report
+    #[clap(long, value_parser = clap::value_parser!(PathBuf))]
+    pub gaps_out: Option<PathBuf>,
+
+    /// Write the samples snapshots disagreed about, and the values taken, as a JSON report
+    #[clap(long, value_parser = clap::value_parser!(PathBuf))]
+    pub reconciliation_out: Option<PathBuf>,
+
+    /// Write metrics in VictoriaMetrics JSON line format
+    #[clap(long, value_parser = clap::value_parser!(PathBuf))]
+    pub victoriametrics_out: Option<PathBuf>,
+
+    /// Append only the samples victoriametrics_out does not have yet, instead of rewriting it
+    #[clap(long)]
+    pub victoriametrics_append: bool,
+
+    /// Write every sample in Prometheus text format, with timestamps
+    #[clap(long, value_parser = clap::value_parser!(PathBuf))]
+    pub prometheus_out: Option<PathBuf>,
+
+    /// Write every sample in OpenMetrics text format, e.g. for promtool backfills
+    #[clap(long, value_parser = clap::value_parser!(PathBuf))]
+    pub openmetrics_out: Option<PathBuf>,
+
+    /// Write the latest value of every series for node_exporter's textfile collector
+    #[clap(long, value_parser = clap::value_parser!(PathBuf))]
+    pub prometheus_textfile_out: Option<PathBuf>,
+
+    /// Write every sample as a row of a single CSV table
+    #[clap(long, value_parser = clap::value_parser!(PathBuf))]
+    pub csv_out: Option<PathBuf>,
+
+    /// Write a CSV table per graph into this directory, with a column per line
+    #[clap(long, value_parser = clap::value_parser!(PathBuf))]
+    pub csv_wide_out: Option<PathBuf>,
+
+    /// Write every sample as Parquet into this directory, partitioned by source
+    #[clap(long, value_parser = clap::value_parser!(PathBuf))]
+    pub parquet_out: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub influx: influx::Args,
+
+    #[command(flatten)]
+    pub victoriametrics: victoriametrics::Args,
+
+    #[command(flatten)]
+    pub remote_write: remote_write::Args,
+
+    #[command(flatten)]
+    pub graphite: graphite::Args,
+}
+
+impl Outputs {
+    /// Fills every output not given here from `defaults`.
+    pub fn with_defaults(&self, defaults: &Outputs) -> Outputs {
+        Outputs {
+            graphs_out: self.graphs_out.clone().or(defaults.graphs_out.clone()),
+            gaps_out: self.gaps_out.clone().or(defaults.gaps_out.clone()),
+            reconciliation_out: self
+                .reconciliation_out
+                .clone()
+                .or(defaults.reconciliation_out.clone()),
+            victoriametrics_out: self
+                .victoriametrics_out
+                .clone()
+                .or(defaults.victoriametrics_out.clone()),
+            victoriametrics_append: self.victoriametrics_append || defaults.victoriametrics_append,
+            prometheus_out: self
+                .prometheus_out
+                .clone()
+                .or(defaults.prometheus_out.clone()),
+            openmetrics_out: self
+                .openmetrics_out
+                .clone()
+                .or(defaults.openmetrics_out.clone()),
+            prometheus_textfile_out: self
+                .prometheus_textfile_out
+                .clone()
+                .or(defaults.prometheus_textfile_out.clone()),
+            csv_out: self.csv_out.clone().or(defaults.csv_out.clone()),
+            csv_wide_out: self.csv_wide_out.clone().or(defaults.csv_wide_out.clone()),
+            parquet_out: self.parquet_out.clone().or(defaults.parquet_out.clone()),
+            influx: self.influx.with_defaults(&defaults.influx),
+            victoriametrics: self
+                .victoriametrics
+                .with_defaults(&defaults.victoriametrics),
+            remote_write: self.remote_write.with_defaults(&defaults.remote_write),
+            graphite: self.graphite.with_defaults(&defaults.graphite),
+        }
+    }
+
+    /// Configured output files by name.
+    pub fn paths(&self) -> Vec<(&'static str, &PathBuf)> {
+        [
+            ("graphs_out", &self.graphs_out),
+            ("gaps_out", &self.gaps_out),
+            ("reconciliation_out", &self.reconciliation_out),
+            ("victoriametrics_out", &self.victoriametrics_out),
+            ("prometheus_out", &self.prometheus_out),
+            ("openmetrics_out", &self.openmetrics_out),
+            ("prometheus_textfile_out", &self.prometheus_textfile_out),
+            ("csv_out", &self.csv_out),
+            ("csv_wide_out", &self.csv_wide_out),
+            ("parquet_out", &self.parquet_out),
+            ("influx.out", &self.influx.out),
+            ("graphite.out", &self.graphite.out),
+        ]
+        .into_iter()
+        .filter_map(|(name, path)| Some((name, path.as_ref()?)))
+        .collect()
+    }
+
+    pub async fn write(&self, processed: &Processed) -> Result<()> {
+        if let Some(graphs_out) = &self.graphs_out {
+            let mut graphs_out = create(graphs_out)?;
+
+            writeln!(
+                &mut graphs_out,
+                "{}",
+                serde_json::to_string_pretty(&processed.graphs)?
+            )?;
+        }
+
+        if let Some(gaps_out) = &self.gaps_out {
+            // graph -> line -> missing days
+            let report = processed
+                .gaps
+                .iter()
+                .map(|(graph, lines)| {
+                    let lines = lines
+                        .iter()
+                        .map(|(label, missing)| {
+                            let days = missing.iter().map(|&ms| to_date(ms)).collect::<Vec<_>>();
+                            (label, days)
+                        })
+                        .collect::<BTreeMap<_, _>>();
+                    (graph, lines)
+                })
+                .collect::<BTreeMap<_, _>>();
+            writeln!(
+                create(gaps_out)?,
+                "{}",
+                serde_json::to_string_pretty(&report)?
+            )?;
+        }
+
+        if let Some(reconciliation_out) = &self.reconciliation_out {
+            writeln!(
+                create(reconciliation_out)?,
+                "{}",
+                serde_json::to_string_pretty(&processed.conflicts)?
+            )?;
+        }
+
+        if let Some(victoriametrics_out) = &self.victoriametrics_out {
+            if self.victoriametrics_append {
+                append_victoriametrics(victoriametrics_out, &processed.victoriametrics)?;
+            } else {
+                write_victoriametrics(victoriametrics_out, &processed.victoriametrics)?;
+            }
+        }
+
+        if let Some(prometheus_out) = &self.prometheus_out {
+            write!(
+                create(prometheus_out)?,
+                "{}",
+                prometheus::render(
+                    &processed.victoriametrics,
+                    &processed.metadata,
+                    Format::Prometheus
+                )?
+            )?;
+        }
+
+        if let Some(openmetrics_out) = &self.openmetrics_out {
+            write!(
+                create(openmetrics_out)?,
+                "{}",
+                prometheus::render(
+                    &processed.victoriametrics,
+                    &processed.metadata,
+                    Format::OpenMetrics
+                )?
+            )?;
+        }
+
+        if let Some(prometheus_textfile_out) = &self.prometheus_textfile_out {
+            // node_exporter may read the file at any time, so it must never
+            // see a partially written one
+            let tmp = prometheus_textfile_out.with_extension("prom.tmp");
+            write!(
+                create(&tmp)?,
+                "{}",
+                prometheus::render_latest(&processed.victoriametrics, &processed.metadata)?
+            )?;
+            fs::rename(&tmp, prometheus_textfile_out).map_err(|e| {
+                anyhow!(
+                    "Unable to write file {}: {}",
+                    prometheus_textfile_out.display(),
+                    e
+                )
+            })?;
+        }
+
+        if let Some(csv_out) = &self.csv_out {
+            write!(
+                create(csv_out)?,
+                "{}",
+                csv::render_long(&processed.victoriametrics)?
+            )?;
+        }
+
+        if let Some(csv_wide_out) = &self.csv_wide_out {
+            fs::create_dir_all(csv_wide_out).map_err(|e| {
+                anyhow!(
+                    "Unable to create directory {}: {}",
+                    csv_wide_out.display(),
+                    e
+                )
+            })?;
+            for (name, graph) in &processed.graphs {
+                let path = csv_wide_out.join(format!("{}.csv", name.replace('/', "_")));
+                write!(create(&path)?, "{}", csv::render_wide(graph)?)?;
+            }
+        }
+
+        if let Some(parquet_out) = &self.parquet_out {
+            parquet::write(parquet_out, &processed.victoriametrics)?;
+        }
+
+        self.influx.write(&processed.victoriametrics).await?;
+        self.victoriametrics
+            .write(&processed.victoriametrics)
+            .await?;
+        self.remote_write.write(&processed.victoriametrics).await?;
+        self.graphite.write(&processed.victoriametrics).await?;
+
+        Ok(())
+    }
+}
+
+fn create(path: &Path) -> Result<fs::File> {
+    fs::File::create(path).map_err(|e| anyhow!("Unable to create file {}: {}", path.display(), e))
+}
+
+/// Writes `victoriametrics` to `path` in VictoriaMetrics JSON line format, a
+/// line per record.
+pub fn write_victoriametrics(path: &Path, victoriametrics: &VictoriaMetrics) -> Result<()> {
+    let mut file = create(path)?;
+    for victoriametric in victoriametrics {
+        serde_json::to_writer(&mut file, victoriametric)?;
+        // above doesn't end in a newline
+        writeln!(&mut file)?;
+    }
+    Ok(())
+}
+
+/// Appends a record to `path` for every series of `victoriametrics` with
+/// samples the file does not have yet, holding only those, so the file only
+/// ever changes at its end. Samples already written are kept as they are,
+/// even if their value changed since, e.g. the score of an anomaly. `compact`
+/// folds the records back into one per series.
+fn append_victoriametrics(path: &Path, victoriametrics: &VictoriaMetrics) -> Result<()> {
+    let written: BTreeMap<Labels, BTreeMap<u64, f64>> = if path.exists() {
+        merge(read_victoriametrics(path)?)?
+            .into_iter()
+            .map(|victoriametric| {
+                let samples = victoriametric
+                    .timestamps
+                    .into_iter()
+                    .zip(victoriametric.values)
+                    .collect();
+                (victoriametric.metric, samples)
+            })
+            .collect()
+    } else {
+        BTreeMap::new()
+    };
+
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| anyhow!("Unable to open file {}: {}", path.display(), e))?;
+    let mut changed = 0;
+    for victoriametric in victoriametrics {
+        let written = written.get(&victoriametric.metric);
+        let (timestamps, values): (Vec<u64>, Vec<f64>) = victoriametric
+            .timestamps
+            .iter()
+            .zip(victoriametric.values.iter())
+            .filter(
+                |(tstamp, value)| match written.and_then(|samples| samples.get(tstamp)) {
+                    Some(v) => {
+                        if v != *value && !(v.is_nan() && value.is_nan()) {
+                            changed += 1;
+                        }
+                        false
+                    }
+                    None => true,
+                },
+            )
+            .unzip();
+        if timestamps.is_empty() {
+            continue;
+        }
+        serde_json::to_writer(
+            &mut file,
+            &VictoriaMetric {
+                metric: victoriametric.metric.clone(),
+                values,
+                timestamps,
+            },
+        )?;
+        writeln!(&mut file)?;
+    }
+    if changed > 0 {
+        info!(
+            "Kept {} samples of {} as written before, their values changed since",
+            changed,
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Retries wait at most 2^6 = 64 seconds.
+const MAX_BACKOFF_EXPONENT: u32 = 6;
+
+/// Sends the request built by `request`, retrying up to `retries` times with
+/// exponential backoff on connection errors, server errors and rate limiting.
+/// The backoff stops growing at [`MAX_BACKOFF_EXPONENT`].
+async fn send(request: impl Fn() -> RequestBuilder, retries: u32) -> Result<Response> {
+    let mut attempt = 0;
+    loop {
+        let error = match request().send().await {
+            Ok(response) if response.status().is_success() => return Ok(response),
+            Ok(response)
+                if !response.status().is_server_error()
+                    && response.status() != StatusCode::TOO_MANY_REQUESTS =>
+            {
+                bail!(
+                    "{} {}",
+                    response.status(),
+                    response.text().await.unwrap_or_default()
+                )
+            }
+            Ok(response) => anyhow!(
+                "{} {}",
+                response.status(),
+                response.text().await.unwrap_or_default()
+            ),
+            Err(e) => anyhow!(e),
+        };
+        if attempt >= retries {
+            return Err(error);
+        }
+        let backoff = Duration::from_secs(1 << attempt.min(MAX_BACKOFF_EXPONENT));
+        warn!("Request failed, retrying in {:?}: {}", backoff, error);
+        tokio::time::sleep(backoff).await;
+        attempt += 1;
+    }
+}
@@ -0,0 +1,159 @@
+use crate::process::{Graph, VictoriaMetrics, MS_PER_DAY};
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Utc};
+use num_traits::ToPrimitive;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Quotes a field if a spreadsheet would otherwise split or misread it.
+fn field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+fn row<S: AsRef<str>>(fields: impl IntoIterator<Item = S>) -> String {
+    let fields: Vec<_> = fields.into_iter().map(|f| field(f.as_ref())).collect();
+    format!("{}\r\n", fields.join(","))
+}
+
+/// Whether every timestamp falls on midnight UTC, as daily data does.
+fn is_daily(mut timestamps: impl Iterator<Item = u64>) -> bool {
+    timestamps.all(|ms| ms % MS_PER_DAY == 0)
+}
+
+/// Formats a timestamp as an ISO 8601 date, or date and time unless `daily`.
+fn iso(ms: u64, daily: bool) -> Result<String> {
+    let datetime = i64::try_from(ms)
+        .ok()
+        .and_then(DateTime::<Utc>::from_timestamp_millis)
+        .ok_or_else(|| anyhow!("timestamp {} is out of range", ms))?;
+    Ok(if daily {
+        datetime.format("%Y-%m-%d").to_string()
+    } else {
+        datetime.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+    })
+}
+
+/// Renders every sample as a row of
+/// `__name__,LABEL...,__timestamp__,__value__`, with a column for every label
+/// any of the series has. The metric, timestamp and value columns are named
+/// like reserved labels, so they cannot clash with a label, e.g. the `metric`
+/// of anomalies.
+pub fn render_long(victoriametrics: &VictoriaMetrics) -> Result<String> {
+    let series = victoriametrics
+        .iter()
+        .map(|victoriametric| (victoriametric.labels(), victoriametric))
+        .collect::<Vec<_>>();
+    let label_names: BTreeSet<_> = series
+        .iter()
+        .flat_map(|(labels, _)| labels.keys())
+        .filter(|name| name.as_str() != "__name__")
+        .collect();
+    if let Some(name) = label_names.iter().find(|name| name.starts_with("__")) {
+        bail!(
+            "label {} is reserved for the columns of the CSV output",
+            name
+        );
+    }
+    let daily = is_daily(
+        victoriametrics
+            .iter()
+            .flat_map(|victoriametric| victoriametric.timestamps.iter().copied()),
+    );
+
+    let mut out = row(["__name__"]
+        .into_iter()
+        .chain(label_names.iter().map(|name| name.as_str()))
+        .chain(["__timestamp__", "__value__"]));
+    for (labels, victoriametric) in &series {
+        for (&tstamp, &value) in victoriametric
+            .timestamps
+            .iter()
+            .zip(victoriametric.values.iter())
+        {
+            out.push_str(&row([labels["__name__"].clone()]
+                .into_iter()
+                .chain(
+                    label_names
+                        .iter()
+                        .map(|name| labels.get(*name).cloned().unwrap_or_default()),
+                )
+                .chain([iso(tstamp, daily)?, value.to_string()])));
+        }
+    }
+    Ok(out)
+}
+
+/// Renders a graph as a table with a `date` column and a column per line,
+/// leaving cells empty where a line has no value.
+pub fn render_wide(graph: &Graph) -> Result<String> {
+    let mut rows = BTreeMap::<u64, Vec<Option<f64>>>::new();
+    for (i, line) in graph.iter().enumerate() {
+        for (x, &y) in line.x.iter().zip(line.y.iter()) {
+            let x = x
+                .to_u64()
+                .ok_or_else(|| anyhow!("Failed casting {:?} to u64", x))?;
+            rows.entry(x).or_insert_with(|| vec![None; graph.len()])[i] = y;
+        }
+    }
+    let daily = is_daily(rows.keys().copied());
+
+    let mut out = row(["date"]
+        .into_iter()
+        .chain(graph.iter().map(|line| line.label.as_str())));
+    for (x, values) in rows {
+        out.push_str(&row([iso(x, daily)?].into_iter().chain(
+            values
+                .into_iter()
+                .map(|value| value.map(|v| v.to_string()).unwrap_or_default()),
+        )));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::{Labels, VictoriaMetric};
+
+    fn victoriametric(labels: Labels, timestamps: Vec<u64>, values: Vec<f64>) -> VictoriaMetric {
+        VictoriaMetric {
+            metric: labels,
+            values,
+            timestamps,
+        }
+    }
+
+    #[test]
+    fn labels_cannot_clash_with_the_fixed_columns() {
+        let pageviews = Labels::new("netlify.pageviews").unwrap();
+        let anomaly = Labels::new("anomaly")
+            .and_then(|labels| labels.with("kind", "outlier"))
+            .and_then(|labels| labels.with("metric", "netlify.pageviews"))
+            .unwrap();
+        let csv = render_long(&vec![
+            victoriametric(pageviews, vec![0, MS_PER_DAY], vec![10.0, 30.0]),
+            victoriametric(anomaly, vec![MS_PER_DAY], vec![30.0]),
+        ])
+        .unwrap();
+        assert_eq!(
+            csv.lines().collect::<Vec<_>>(),
+            [
+                "__name__,kind,metric,__timestamp__,__value__",
+                "netlify.pageviews,,,1970-01-01,10",
+                "netlify.pageviews,,,1970-01-02,30",
+                "anomaly,outlier,netlify.pageviews,1970-01-02,30",
+            ]
+        );
+    }
+
+    #[test]
+    fn reserved_labels_are_rejected() {
+        let labels = Labels::new("gtrends")
+            .and_then(|labels| labels.with("__value__", "x"))
+            .unwrap();
+        assert!(render_long(&vec![victoriametric(labels, vec![0], vec![1.0])]).is_err());
+    }
+}
@@ -0,0 +1,311 @@
+use crate::process::VictoriaMetrics;
+use anyhow::{anyhow, bail, Result};
+use clap::ValueEnum;
+use log::{info, warn};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+
+/// Datapoints per pickle message, carbon rejects overly large messages.
+const PICKLE_BATCH_SIZE: usize = 500;
+
+/// How datapoints are encoded for carbon.
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    /// `path value timestamp` lines, usually on port 2003
+    #[default]
+    Plaintext,
+    /// Length prefixed pickled lists of datapoints, usually on port 2004
+    Pickle,
+}
+
+/// Graphite outputs.
+#[derive(clap::Args, Deserialize, Debug, Clone, Default)]
+#[group(id = "graphite")]
+#[command(next_help_heading = "Graphite")]
+#[serde(default, deny_unknown_fields)]
+pub struct Args {
+    /// Write what would be sent to carbon into a file, to replay it later, e.g. with `nc`
+    #[clap(id = "graphite_out", long = "graphite-out", value_parser = clap::value_parser!(PathBuf))]
+    pub out: Option<PathBuf>,
+
+    /// Carbon to send metrics to, e.g. localhost:2003
+    #[clap(id = "graphite_address", long = "graphite-address")]
+    pub address: Option<String>,
+
+    /// Protocol spoken with carbon and written to the file [default: plaintext]
+    #[clap(id = "graphite_protocol", long = "graphite-protocol", value_enum)]
+    pub protocol: Option<Protocol>,
+
+    /// Path prepended to every metric, e.g. nixos
+    #[clap(id = "graphite_prefix", long = "graphite-prefix")]
+    pub prefix: Option<String>,
+
+    /// Path of a metric as METRIC=PATTERN, e.g.
+    /// netlify.sources=netlify.referrers.{source}; `{__name__}` and `{LABEL}`
+    /// are replaced. Without one, label values are appended in label order
+    #[clap(id = "graphite_template", long = "graphite-template")]
+    pub templates: Vec<String>,
+
+    /// Replacement for characters not allowed in a path segment [default: _]
+    #[clap(id = "graphite_escape", long = "graphite-escape")]
+    pub escape: Option<String>,
+}
+
+impl Args {
+    /// Fills every argument not given here from `defaults`.
+    pub fn with_defaults(&self, defaults: &Args) -> Args {
+        Args {
+            out: self.out.clone().or(defaults.out.clone()),
+            address: self.address.clone().or(defaults.address.clone()),
+            protocol: self.protocol.or(defaults.protocol),
+            prefix: self.prefix.clone().or(defaults.prefix.clone()),
+            templates: if self.templates.is_empty() {
+                defaults.templates.clone()
+            } else {
+                self.templates.clone()
+            },
+            escape: self.escape.clone().or(defaults.escape.clone()),
+        }
+    }
+
+    fn templates(&self) -> Result<BTreeMap<&str, &str>> {
+        self.templates
+            .iter()
+            .map(|template| {
+                template.split_once('=').ok_or_else(|| {
+                    anyhow!(
+                        "Invalid Graphite template {}: expected METRIC=PATTERN",
+                        template
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// Escapes a single path segment, so `github.com` stays one segment.
+    fn escape(&self, segment: &str) -> String {
+        let replacement = self.escape.as_deref().unwrap_or("_");
+        let mut escaped = String::with_capacity(segment.len());
+        for c in segment.chars() {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                escaped.push(c);
+            } else {
+                escaped.push_str(replacement);
+            }
+        }
+        escaped
+    }
+
+    /// Escapes a metric name, whose dots do separate segments.
+    fn escape_name(&self, name: &str) -> String {
+        name.split('.')
+            .map(|segment| self.escape(segment))
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+
+    /// Dotted path of a series, from its template or its name followed by
+    /// its label values.
+    fn path(
+        &self,
+        labels: &BTreeMap<String, String>,
+        templates: &BTreeMap<&str, &str>,
+    ) -> Result<String> {
+        let name = &labels["__name__"];
+        let mut path = match templates.get(name.as_str()) {
+            Some(pattern) => {
+                let mut path = String::new();
+                let mut rest = *pattern;
+                while let Some(start) = rest.find('{') {
+                    path.push_str(&rest[..start]);
+                    let end = rest[start..].find('}').ok_or_else(|| {
+                        anyhow!("Invalid Graphite template pattern {}: unclosed {{", pattern)
+                    })? + start;
+                    let label = &rest[start + 1..end];
+                    let value = labels.get(label).ok_or_else(|| {
+                        anyhow!(
+                            "Graphite template pattern {} uses label {}, which {} does not have",
+                            pattern,
+                            label,
+                            name
+                        )
+                    })?;
+                    if label == "__name__" {
+                        path.push_str(&self.escape_name(value));
+                    } else {
+                        path.push_str(&self.escape(value));
+                    }
+                    rest = &rest[end + 1..];
+                }
+                path.push_str(rest);
+                path
+            }
+            None => {
+                let mut segments = vec![self.escape_name(name)];
+                segments.extend(
+                    labels
+                        .iter()
+                        .filter(|(label, _)| label.as_str() != "__name__")
+                        .map(|(_, value)| self.escape(value)),
+                );
+                segments.join(".")
+            }
+        };
+        if let Some(prefix) = &self.prefix {
+            path = format!("{}.{}", prefix, path);
+        }
+        Ok(path)
+    }
+
+    /// Every datapoint as (path, timestamp in seconds, value). Graphite has
+    /// no way to store NaN or infinity, so such samples are left out.
+    fn datapoints(&self, victoriametrics: &VictoriaMetrics) -> Result<Vec<(String, u64, f64)>> {
+        let templates = self.templates()?;
+        let mut paths = HashSet::new();
+        let mut datapoints = vec![];
+        for victoriametric in victoriametrics {
+            let path = self.path(victoriametric.labels(), &templates)?;
+            if !paths.insert(path.clone()) {
+                bail!(
+                    "metric {} maps to Graphite path {} like another one, adjust --graphite-template",
+                    victoriametric.metric,
+                    path
+                );
+            }
+            for (&tstamp, &value) in victoriametric
+                .timestamps
+                .iter()
+                .zip(victoriametric.values.iter())
+            {
+                if !value.is_finite() {
+                    warn!("Skipping {} at {} for Graphite: {}", path, tstamp, value);
+                    continue;
+                }
+                datapoints.push((path.clone(), tstamp / 1000, value));
+            }
+        }
+        Ok(datapoints)
+    }
+
+    pub async fn write(&self, victoriametrics: &VictoriaMetrics) -> Result<()> {
+        if self.out.is_none() && self.address.is_none() {
+            return Ok(());
+        }
+        let datapoints = self.datapoints(victoriametrics)?;
+        let payload = match self.protocol.unwrap_or_default() {
+            Protocol::Plaintext => plaintext(&datapoints),
+            Protocol::Pickle => pickle(&datapoints),
+        };
+
+        if let Some(out) = &self.out {
+            fs::write(out, &payload)
+                .map_err(|e| anyhow!("Unable to write file {}: {}", out.display(), e))?;
+        }
+
+        if let Some(address) = &self.address {
+            let mut stream = TcpStream::connect(address)
+                .await
+                .map_err(|e| anyhow!("Unable to connect to {}: {}", address, e))?;
+            stream.write_all(&payload).await?;
+            stream.shutdown().await?;
+            info!("Sent {} datapoints to {}", datapoints.len(), address);
+        }
+
+        Ok(())
+    }
+}
+
+fn plaintext(datapoints: &[(String, u64, f64)]) -> Vec<u8> {
+    let mut payload = String::new();
+    for (path, tstamp, value) in datapoints {
+        payload.push_str(&format!("{} {:?} {}\n", path, value, tstamp));
+    }
+    payload.into_bytes()
+}
+
+/// Pickles datapoints the way carbon's pickle receiver expects them, a list
+/// of `(path, (timestamp, value))` tuples prefixed with its length, in as
+/// many messages as needed.
+fn pickle(datapoints: &[(String, u64, f64)]) -> Vec<u8> {
+    let mut payload = vec![];
+    for batch in datapoints.chunks(PICKLE_BATCH_SIZE) {
+        // protocol 2, empty list, mark
+        let mut message = vec![0x80, 2, b']', b'('];
+        for (path, tstamp, value) in batch {
+            message.push(b'X'); // BINUNICODE
+            message.extend((path.len() as u32).to_le_bytes());
+            message.extend(path.as_bytes());
+            message.push(b'G'); // BINFLOAT
+            message.extend((*tstamp as f64).to_be_bytes());
+            message.push(b'G');
+            message.extend(value.to_be_bytes());
+            message.push(0x86); // TUPLE2, (timestamp, value)
+            message.push(0x86); // TUPLE2, (path, (timestamp, value))
+        }
+        message.push(b'e'); // APPENDS
+        message.push(b'.'); // STOP
+        payload.extend((message.len() as u32).to_be_bytes());
+        payload.extend(message);
+    }
+    payload
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn datapoints_are_pickled_as_carbon_expects() {
+        let payload = pickle(&[("nixos.pageviews".to_owned(), 1675209600, 1.5)]);
+        let mut message = vec![0x80, 2, b']', b'(', b'X', 15, 0, 0, 0];
+        message.extend(b"nixos.pageviews");
+        message.push(b'G');
+        message.extend(1675209600f64.to_be_bytes());
+        message.push(b'G');
+        message.extend(1.5f64.to_be_bytes());
+        message.extend([0x86, 0x86, b'e', b'.']);
+        let mut expected = (message.len() as u32).to_be_bytes().to_vec();
+        expected.extend(message);
+        assert_eq!(payload, expected);
+    }
+
+    #[test]
+    fn large_pickles_are_split_into_messages() {
+        let datapoints: Vec<_> = (0..PICKLE_BATCH_SIZE as u64 + 1)
+            .map(|tstamp| ("gtrends.nixos".to_owned(), tstamp, 1.0))
+            .collect();
+        let payload = pickle(&datapoints);
+
+        // BINUNICODE with its length and the path, two BINFLOATs, two TUPLE2s
+        let tuple = 1 + 4 + "gtrends.nixos".len() + 2 * (1 + 8) + 2;
+        let mut rest = payload.as_slice();
+        let mut tuples = vec![];
+        while !rest.is_empty() {
+            let (length, after) = rest.split_at(4);
+            let length = u32::from_be_bytes(length.try_into().unwrap()) as usize;
+            let (message, after) = after.split_at(length);
+            assert!(message.starts_with(&[0x80, 2, b']', b'(']));
+            assert!(message.ends_with(b"e."));
+            tuples.push((message.len() - 6) / tuple);
+            rest = after;
+        }
+        assert_eq!(tuples, [PICKLE_BATCH_SIZE, 1]);
+    }
+
+    #[test]
+    fn plaintext_has_a_line_per_datapoint() {
+        let payload = plaintext(&[
+            ("nixos.visitors".to_owned(), 1675209600, 12.0),
+            ("nixos.visitors".to_owned(), 1675296000, 0.25),
+        ]);
+        assert_eq!(
+            String::from_utf8(payload).unwrap(),
+            "nixos.visitors 12.0 1675209600\nnixos.visitors 0.25 1675296000\n"
+        );
+    }
+}
@@ -0,0 +1,222 @@
+use crate::process::VictoriaMetrics;
+use anyhow::{anyhow, bail, Result};
+use clap::ValueEnum;
+use log::{info, warn};
+use reqwest::Client;
+use serde::Deserialize;
+use std::fs;
+use std::path::PathBuf;
+
+pub mod receive;
+
+const DEFAULT_BATCH_SIZE: usize = 5000;
+
+/// Unit of the timestamps in line protocol.
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Precision {
+    #[default]
+    Ns,
+    Us,
+    Ms,
+    S,
+}
+
+impl Precision {
+    fn timestamp(&self, ms: u64) -> u64 {
+        match self {
+            Precision::Ns => ms * 1_000_000,
+            Precision::Us => ms * 1_000,
+            Precision::Ms => ms,
+            Precision::S => ms / 1_000,
+        }
+    }
+
+    /// How the `/api/v2/write` endpoint calls this precision.
+    fn v2(&self) -> &'static str {
+        match self {
+            Precision::Ns => "ns",
+            Precision::Us => "us",
+            Precision::Ms => "ms",
+            Precision::S => "s",
+        }
+    }
+
+    /// How the InfluxDB 1 `/write` endpoint calls this precision.
+    fn v1(&self) -> &'static str {
+        match self {
+            Precision::Ns => "n",
+            Precision::Us => "u",
+            Precision::Ms => "ms",
+            Precision::S => "s",
+        }
+    }
+}
+
+/// InfluxDB line protocol outputs.
+#[derive(clap::Args, Deserialize, Debug, Clone, Default)]
+#[group(id = "influx")]
+#[command(next_help_heading = "InfluxDB")]
+#[serde(default, deny_unknown_fields)]
+pub struct Args {
+    /// Write metrics in InfluxDB line protocol
+    #[clap(id = "influx_out", long = "influx-out", value_parser = clap::value_parser!(PathBuf))]
+    pub out: Option<PathBuf>,
+
+    /// Timestamp precision of the line protocol [default: ns]
+    #[clap(id = "influx_precision", long = "influx-precision", value_enum)]
+    pub precision: Option<Precision>,
+
+    /// Base URL of an InfluxDB to write metrics to, e.g. http://localhost:8086
+    #[clap(id = "influx_url", long = "influx-url")]
+    pub url: Option<String>,
+
+    /// InfluxDB 2 bucket to write to
+    #[clap(id = "influx_bucket", long = "influx-bucket")]
+    pub bucket: Option<String>,
+
+    /// InfluxDB 2 organization of the bucket
+    #[clap(id = "influx_org", long = "influx-org")]
+    pub org: Option<String>,
+
+    /// InfluxDB 1 database to write to, when not using a bucket
+    #[clap(id = "influx_database", long = "influx-database")]
+    pub database: Option<String>,
+
+    /// InfluxDB API token
+    #[clap(id = "influx_token", long = "influx-token")]
+    pub token: Option<String>,
+
+    /// Number of lines sent per request [default: 5000]
+    #[clap(id = "influx_batch_size", long = "influx-batch-size")]
+    pub batch_size: Option<usize>,
+}
+
+impl Args {
+    /// Fills every argument not given here from `defaults`.
+    pub fn with_defaults(&self, defaults: &Args) -> Args {
+        Args {
+            out: self.out.clone().or(defaults.out.clone()),
+            precision: self.precision.or(defaults.precision),
+            url: self.url.clone().or(defaults.url.clone()),
+            bucket: self.bucket.clone().or(defaults.bucket.clone()),
+            org: self.org.clone().or(defaults.org.clone()),
+            database: self.database.clone().or(defaults.database.clone()),
+            token: self.token.clone().or(defaults.token.clone()),
+            batch_size: self.batch_size.or(defaults.batch_size),
+        }
+    }
+
+    pub async fn write(&self, victoriametrics: &VictoriaMetrics) -> Result<()> {
+        if self.out.is_none() && self.url.is_none() {
+            return Ok(());
+        }
+        let precision = self.precision.unwrap_or_default();
+        let lines = render(victoriametrics, precision)?;
+
+        if let Some(out) = &self.out {
+            let mut text = lines.join("\n");
+            text.push('\n');
+            fs::write(out, text)
+                .map_err(|e| anyhow!("Unable to write file {}: {}", out.display(), e))?;
+        }
+
+        if let Some(url) = &self.url {
+            let url = url.trim_end_matches('/');
+            let (endpoint, mut query) = match (&self.bucket, &self.database) {
+                (Some(bucket), _) => (
+                    format!("{}/api/v2/write", url),
+                    vec![("bucket", bucket.as_str()), ("precision", precision.v2())],
+                ),
+                (None, Some(database)) => (
+                    format!("{}/write", url),
+                    vec![("db", database.as_str()), ("precision", precision.v1())],
+                ),
+                (None, None) => bail!(
+                    "Writing to InfluxDB needs --influx-bucket (InfluxDB 2) or --influx-database (InfluxDB 1)"
+                ),
+            };
+            if let Some(org) = &self.org {
+                query.push(("org", org.as_str()));
+            }
+
+            let client = Client::new();
+            let batch_size = self.batch_size.unwrap_or(DEFAULT_BATCH_SIZE).max(1);
+            for batch in lines.chunks(batch_size) {
+                let mut request = client
+                    .post(&endpoint)
+                    .query(&query)
+                    .header("Content-Type", "text/plain; charset=utf-8")
+                    .body(batch.join("\n"));
+                if let Some(token) = &self.token {
+                    request = request.header("Authorization", format!("Token {}", token));
+                }
+                let response = request
+                    .send()
+                    .await
+                    .map_err(|e| anyhow!("Failed writing to {}: {}", endpoint, e))?;
+                let status = response.status();
+                if !status.is_success() {
+                    bail!(
+                        "Failed writing to {}: {} {}",
+                        endpoint,
+                        status,
+                        response.text().await.unwrap_or_default()
+                    );
+                }
+            }
+            info!("Wrote {} lines to {}", lines.len(), endpoint);
+        }
+
+        Ok(())
+    }
+}
+
+fn escape(s: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Renders every sample as a line of InfluxDB line protocol, using the metric
+/// name as measurement, the labels as tags and the value as the `value` field.
+///
+/// Line protocol has no way to represent NaN or infinity, so such samples
+/// are left out.
+pub fn render(victoriametrics: &VictoriaMetrics, precision: Precision) -> Result<Vec<String>> {
+    let mut lines = vec![];
+    for victoriametric in victoriametrics {
+        let labels = victoriametric.labels();
+        let mut series = escape(&labels["__name__"], &[',', ' ']);
+        for (name, value) in labels.labels() {
+            series.push_str(&format!(
+                ",{}={}",
+                escape(name, &[',', '=', ' ']),
+                escape(value, &[',', '=', ' '])
+            ));
+        }
+
+        for (&tstamp, &value) in victoriametric
+            .timestamps
+            .iter()
+            .zip(victoriametric.values.iter())
+        {
+            if !value.is_finite() {
+                warn!("Skipping {} at {} for InfluxDB: {}", series, tstamp, value);
+                continue;
+            }
+            lines.push(format!(
+                "{} value={:?} {}",
+                series,
+                value,
+                precision.timestamp(tstamp)
+            ));
+        }
+    }
+    Ok(lines)
+}
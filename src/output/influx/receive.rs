@@ -0,0 +1,186 @@
+use crate::output::influx::{render, Precision};
+use crate::process::read_victoriametrics;
+use anyhow::{anyhow, bail, Result};
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    routing::post,
+    Router,
+};
+use clap::Parser;
+use log::{error, info};
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, oneshot};
+
+#[derive(Parser, Debug, Clone, Default)]
+#[command(version, about, author, long_about = None)]
+pub struct Cli {
+    /// Address to receive writes on at `/api/v2/write` and `/write` [default: 127.0.0.1:8086]
+    #[clap(long)]
+    listen: Option<SocketAddr>,
+
+    /// Timestamp precision every write has to declare [default: ns]
+    #[clap(long, value_enum)]
+    precision: Option<Precision>,
+
+    /// Most lines a single write may have
+    #[clap(long)]
+    batch_size: Option<usize>,
+
+    /// VictoriaMetrics JSON line files with exactly the samples expected to be
+    /// received; exits once all of them arrived and fails on any other line
+    #[clap(long, value_parser = clap::value_parser!(PathBuf))]
+    expect: Vec<PathBuf>,
+}
+
+struct Receiver {
+    precision: Precision,
+    batch_size: Option<usize>,
+    /// Lines of the expected samples, in the precision expected
+    expected: Option<BTreeSet<String>>,
+    received: BTreeSet<String>,
+    done: mpsc::UnboundedSender<Result<()>>,
+}
+
+type Shared = Arc<Mutex<Receiver>>;
+
+/// Checks a write follows the protocol of its endpoint, the InfluxDB 2 one
+/// if `v2`, with the precision and batch size expected. Returns its lines.
+fn decode<'a>(
+    receiver: &Receiver,
+    v2: bool,
+    query: &BTreeMap<String, String>,
+    body: &'a str,
+) -> Result<Vec<&'a str>> {
+    let (target, precision) = if v2 {
+        ("bucket", receiver.precision.v2())
+    } else {
+        ("db", receiver.precision.v1())
+    };
+    if !query.contains_key(target) {
+        bail!("no {} given", target);
+    }
+    let declared = query.get("precision").map(String::as_str);
+    if declared != Some(precision) {
+        bail!("expected precision {}, got {:?}", precision, declared);
+    }
+
+    let lines: Vec<_> = body.lines().filter(|line| !line.is_empty()).collect();
+    if let Some(batch_size) = receiver.batch_size {
+        if lines.len() > batch_size {
+            bail!("expected at most {} lines, got {}", batch_size, lines.len());
+        }
+    }
+    Ok(lines)
+}
+
+/// Checks received lines against the expected ones, remembering which
+/// arrived. Returns whether all expected lines have been received.
+fn check(receiver: &mut Receiver, lines: &[&str]) -> Result<bool> {
+    let expected = match &receiver.expected {
+        Some(expected) => expected,
+        None => return Ok(false),
+    };
+    for line in lines {
+        if !expected.contains(*line) {
+            bail!("unexpected line {}", line);
+        }
+        receiver.received.insert(line.to_string());
+    }
+    Ok(receiver.received.len() == expected.len())
+}
+
+fn receive(
+    receiver: &Shared,
+    v2: bool,
+    query: &BTreeMap<String, String>,
+    body: &str,
+) -> (StatusCode, String) {
+    let mut receiver = receiver.lock().expect("receiver lock poisoned");
+    let lines = match decode(&receiver, v2, query, body) {
+        Ok(lines) => lines,
+        Err(e) => {
+            error!("Invalid write: {:#}", e);
+            return (StatusCode::BAD_REQUEST, format!("{:#}\n", e));
+        }
+    };
+    for line in &lines {
+        println!("{}", line);
+    }
+
+    match check(&mut receiver, &lines) {
+        Ok(false) => (StatusCode::NO_CONTENT, String::new()),
+        Ok(true) => {
+            let _ = receiver.done.send(Ok(()));
+            (StatusCode::NO_CONTENT, String::new())
+        }
+        Err(e) => {
+            let response = (StatusCode::BAD_REQUEST, format!("{:#}\n", e));
+            let _ = receiver.done.send(Err(e));
+            response
+        }
+    }
+}
+
+async fn v2_handler(
+    State(receiver): State<Shared>,
+    Query(query): Query<BTreeMap<String, String>>,
+    body: String,
+) -> (StatusCode, String) {
+    receive(&receiver, true, &query, &body)
+}
+
+async fn v1_handler(
+    State(receiver): State<Shared>,
+    Query(query): Query<BTreeMap<String, String>>,
+    body: String,
+) -> (StatusCode, String) {
+    receive(&receiver, false, &query, &body)
+}
+
+pub async fn run(args: &Cli) -> Result<()> {
+    let precision = args.precision.unwrap_or_default();
+    let expected = if args.expect.is_empty() {
+        None
+    } else {
+        let mut expected = BTreeSet::new();
+        for path in &args.expect {
+            expected.extend(render(&read_victoriametrics(path)?, precision)?);
+        }
+        Some(expected)
+    };
+
+    let (done, mut finished) = mpsc::unbounded_channel();
+    let app = Router::new()
+        .route("/api/v2/write", post(v2_handler))
+        .route("/write", post(v1_handler))
+        .with_state(Arc::new(Mutex::new(Receiver {
+            precision,
+            batch_size: args.batch_size,
+            expected,
+            received: BTreeSet::new(),
+            done,
+        })));
+    let listen = args
+        .listen
+        .unwrap_or_else(|| SocketAddr::from(([127, 0, 0, 1], 8086)));
+    let listener = tokio::net::TcpListener::bind(listen)
+        .await
+        .map_err(|e| anyhow!("Unable to listen on {}: {}", listen, e))?;
+    info!("Receiving InfluxDB writes on http://{}", listen);
+
+    // finish answering the last request before exiting, the writer fails on
+    // a dropped connection otherwise
+    let (result, outcome) = oneshot::channel();
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async move {
+            let _ = result.send(finished.recv().await.unwrap_or(Ok(())));
+        })
+        .await?;
+    outcome.await.unwrap_or(Ok(()))?;
+    info!("Received every expected line");
+    Ok(())
+}
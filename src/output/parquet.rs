@@ -0,0 +1,140 @@
+use crate::process::{Labels, VictoriaMetric, VictoriaMetrics};
+use anyhow::{anyhow, Result};
+use arrow::array::{
+    ArrayRef, Float64Array, MapBuilder, StringArray, StringBuilder, TimestampMillisecondArray,
+};
+use arrow::datatypes::{Field, Schema};
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Source a series belongs to, the part of its metric's name before the
+/// first `.`, e.g. `netlify` for `netlify.pageviews`. Growth and anomalies
+/// belong to the source of the metric they are of.
+fn source(labels: &Labels) -> &str {
+    let name = match labels.name() {
+        "anomaly" => labels.get("metric").map_or("anomaly", String::as_str),
+        name => name.strip_suffix("_growth").unwrap_or(name),
+    };
+    name.split('.').next().unwrap_or(name)
+}
+
+/// A row per sample with columns `timestamp` (milliseconds, UTC), `metric`,
+/// `labels` (a map, without `__name__`) and `value`.
+fn record_batch(victoriametrics: &[&VictoriaMetric]) -> Result<RecordBatch> {
+    let mut timestamps = vec![];
+    let mut metrics = vec![];
+    let mut labels = MapBuilder::new(None, StringBuilder::new(), StringBuilder::new());
+    let mut values = vec![];
+    for victoriametric in victoriametrics {
+        let series = victoriametric.labels();
+        for (&tstamp, &value) in victoriametric
+            .timestamps
+            .iter()
+            .zip(victoriametric.values.iter())
+        {
+            timestamps.push(
+                i64::try_from(tstamp)
+                    .map_err(|_| anyhow!("timestamp {} is out of range", tstamp))?,
+            );
+            metrics.push(series["__name__"].clone());
+            for (name, label) in series.iter().filter(|(name, _)| *name != "__name__") {
+                labels.keys().append_value(name);
+                labels.values().append_value(label);
+            }
+            labels.append(true)?;
+            values.push(value);
+        }
+    }
+
+    let columns: Vec<(&str, ArrayRef)> = vec![
+        (
+            "timestamp",
+            Arc::new(TimestampMillisecondArray::from(timestamps).with_timezone("UTC")),
+        ),
+        ("metric", Arc::new(StringArray::from(metrics))),
+        ("labels", Arc::new(labels.finish())),
+        ("value", Arc::new(Float64Array::from(values))),
+    ];
+    let schema = Schema::new(
+        columns
+            .iter()
+            .map(|(name, column)| Field::new(*name, column.data_type().clone(), false))
+            .collect::<Vec<_>>(),
+    );
+    Ok(RecordBatch::try_new(
+        Arc::new(schema),
+        columns.into_iter().map(|(_, column)| column).collect(),
+    )?)
+}
+
+/// Writes every sample into `dir/source=SOURCE/data.parquet`, a file per
+/// source in the hive partitioning layout Polars and DuckDB understand.
+pub fn write(dir: &Path, victoriametrics: &VictoriaMetrics) -> Result<()> {
+    let mut sources = BTreeMap::<String, Vec<&VictoriaMetric>>::new();
+    for victoriametric in victoriametrics {
+        sources
+            .entry(source(victoriametric.labels()).to_owned())
+            .or_default()
+            .push(victoriametric);
+    }
+
+    for (source, victoriametrics) in sources {
+        let partition = dir.join(format!("source={}", source));
+        fs::create_dir_all(&partition)
+            .map_err(|e| anyhow!("Unable to create directory {}: {}", partition.display(), e))?;
+        let path = partition.join("data.parquet");
+        let file = fs::File::create(&path)
+            .map_err(|e| anyhow!("Unable to create file {}: {}", path.display(), e))?;
+
+        let batch = record_batch(&victoriametrics)?;
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+        let mut writer = ArrowWriter::try_new(file, batch.schema(), Some(properties))?;
+        writer.write(&batch)?;
+        writer
+            .close()
+            .map_err(|e| anyhow!("Unable to write file {}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derived_series_belong_to_their_metric_source() {
+        let labels = |name: &str, extra: &[(&str, &str)]| {
+            extra
+                .iter()
+                .try_fold(Labels::new(name).unwrap(), |labels, (name, value)| {
+                    labels.with(*name, *value)
+                })
+                .unwrap()
+        };
+        assert_eq!(source(&labels("netlify.pageviews", &[])), "netlify");
+        assert_eq!(source(&labels("gtrends", &[])), "gtrends");
+        assert_eq!(
+            source(&labels("gtrends_growth", &[("period", "year")])),
+            "gtrends"
+        );
+        assert_eq!(
+            source(&labels("netlify.visitors_growth", &[("period", "week")])),
+            "netlify"
+        );
+        assert_eq!(
+            source(&labels(
+                "anomaly",
+                &[("metric", "netlify.pageviews"), ("kind", "outlier")]
+            )),
+            "netlify"
+        );
+    }
+}
@@ -0,0 +1,193 @@
+use crate::process::{series::Metadatas, VictoriaMetrics};
+use anyhow::{bail, Result};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Flavour of the text exposition format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Prometheus text format 0.0.4, timestamps in milliseconds
+    Prometheus,
+    /// OpenMetrics 1.0, timestamps in seconds and terminated by `# EOF`
+    OpenMetrics,
+}
+
+/// Turns `name` into a valid Prometheus metric name by replacing every
+/// character that is not allowed with `_`, e.g. `netlify.pageviews` becomes
+/// `netlify_pageviews`.
+pub fn metric_name(name: &str) -> String {
+    sanitize(name, |c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+}
+
+/// Like [`metric_name`], but for label names, which may not contain `:`.
+pub fn label_name(name: &str) -> String {
+    sanitize(name, |c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn sanitize(name: &str, allowed: impl Fn(char) -> bool) -> String {
+    let mut sanitized: String = name
+        .chars()
+        .map(|c| if allowed(c) { c } else { '_' })
+        .collect();
+    if sanitized.is_empty() || sanitized.starts_with(|c: char| c.is_ascii_digit()) {
+        sanitized.insert(0, '_');
+    }
+    sanitized
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Renders labels as `{name="value",...}`, leaving out `__name__`. Two
+/// label names that are the same once sanitised are an error.
+pub fn labels(labels: &BTreeMap<String, String>) -> Result<String> {
+    let mut names = BTreeMap::new();
+    let mut rendered = vec![];
+    for (name, value) in labels {
+        if name == "__name__" {
+            continue;
+        }
+        let sanitised = label_name(name);
+        if let Some(other) = names.insert(sanitised.clone(), name) {
+            bail!(
+                "labels {} and {} are the same label once sanitised for Prometheus",
+                other,
+                name
+            );
+        }
+        rendered.push(format!("{}=\"{}\"", sanitised, escape_label_value(value)));
+    }
+    Ok(if rendered.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", rendered.join(","))
+    })
+}
+
+pub fn value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_owned()
+    } else if value.is_infinite() {
+        if value > 0.0 { "+Inf" } else { "-Inf" }.to_owned()
+    } else {
+        value.to_string()
+    }
+}
+
+fn timestamp(ms: u64, format: Format) -> String {
+    match format {
+        Format::Prometheus => ms.to_string(),
+        Format::OpenMetrics => format!("{}.{:03}", ms / 1000, ms % 1000),
+    }
+}
+
+/// All series sharing a metric name, once sanitised.
+struct Family {
+    /// Name before sanitising
+    original: String,
+    /// Rendered labels -> samples
+    series: BTreeMap<String, BTreeMap<u64, f64>>,
+}
+
+/// Series by family. Metrics that are the same once sanitised, e.g.
+/// `netlify.pageviews` and `netlify_pageviews`, are an error rather than
+/// having their samples mixed.
+fn families(victoriametrics: &VictoriaMetrics) -> Result<BTreeMap<String, Family>> {
+    let mut families = BTreeMap::<String, Family>::new();
+    for victoriametric in victoriametrics {
+        let labels_ = victoriametric.labels();
+        let name = &labels_["__name__"];
+        let family = families.entry(metric_name(name)).or_insert_with(|| Family {
+            original: name.clone(),
+            series: BTreeMap::new(),
+        });
+        if &family.original != name {
+            bail!(
+                "metrics {} and {} are the same metric once sanitised for Prometheus",
+                family.original,
+                name
+            );
+        }
+        family.series.entry(labels(labels_)?).or_default().extend(
+            victoriametric
+                .timestamps
+                .iter()
+                .copied()
+                .zip(victoriametric.values.iter().copied()),
+        );
+    }
+    Ok(families)
+}
+
+fn write_metadata(
+    out: &mut String,
+    name: &str,
+    family: &Family,
+    metadata: &Metadatas,
+) -> Result<()> {
+    match metadata.get(&family.original) {
+        Some(metadata) => writeln!(
+            out,
+            "# HELP {} {} ({})",
+            name,
+            metadata
+                .description
+                .replace('\\', "\\\\")
+                .replace('\n', "\\n"),
+            metadata.unit
+        )?,
+        None => writeln!(out, "# HELP {} Value of {}", name, family.original)?,
+    }
+    writeln!(out, "# TYPE {} gauge", name)?;
+    Ok(())
+}
+
+/// Renders every sample of every series with its timestamp, e.g. for
+/// backfilling with `promtool tsdb create-blocks-from openmetrics`. Metrics
+/// in `metadata` are described by it.
+pub fn render(
+    victoriametrics: &VictoriaMetrics,
+    metadata: &Metadatas,
+    format: Format,
+) -> Result<String> {
+    let mut out = String::new();
+    for (name, family) in families(victoriametrics)? {
+        write_metadata(&mut out, &name, &family, metadata)?;
+        for (labels, samples) in &family.series {
+            for (&tstamp, &sample) in samples {
+                writeln!(
+                    out,
+                    "{}{} {} {}",
+                    name,
+                    labels,
+                    value(sample),
+                    timestamp(tstamp, format)
+                )?;
+            }
+        }
+    }
+    if format == Format::OpenMetrics {
+        writeln!(out, "# EOF")?;
+    }
+    Ok(out)
+}
+
+/// Renders the latest sample of every series as a gauge in the Prometheus
+/// text exposition format, without timestamps, as an exporter would. This is
+/// also what node_exporter's textfile collector accepts.
+pub fn render_latest(victoriametrics: &VictoriaMetrics, metadata: &Metadatas) -> Result<String> {
+    let mut out = String::new();
+    for (name, family) in families(victoriametrics)? {
+        write_metadata(&mut out, &name, &family, metadata)?;
+        for (labels, samples) in &family.series {
+            if let Some((_, &latest)) = samples.last_key_value() {
+                writeln!(out, "{}{} {}", name, labels, value(latest))?;
+            }
+        }
+    }
+    Ok(out)
+}
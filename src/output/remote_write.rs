@@ -0,0 +1,234 @@
+use crate::output::prometheus::metric_name;
+use crate::process::{is_valid_label_name, VictoriaMetric, VictoriaMetrics, MS_PER_DAY};
+use anyhow::{anyhow, bail, Result};
+use log::info;
+use prost::Message;
+use reqwest::Client;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashSet};
+
+pub mod receive;
+
+const DEFAULT_BATCH_DAYS: u64 = 30;
+const DEFAULT_RETRIES: u32 = 3;
+// Messages of the remote write 1.0 protocol, see
+// https://prometheus.io/docs/specs/remote_write_spec/
+
+#[derive(Clone, PartialEq, Message)]
+pub struct WriteRequest {
+    #[prost(message, repeated, tag = "1")]
+    pub timeseries: Vec<TimeSeries>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct TimeSeries {
+    /// Sorted by name, `__name__` included
+    #[prost(message, repeated, tag = "1")]
+    pub labels: Vec<Label>,
+    /// Sorted by timestamp
+    #[prost(message, repeated, tag = "2")]
+    pub samples: Vec<Sample>,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Message)]
+pub struct Label {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub value: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Sample {
+    #[prost(double, tag = "1")]
+    pub value: f64,
+    /// Milliseconds since the epoch
+    #[prost(int64, tag = "2")]
+    pub timestamp: i64,
+}
+
+impl WriteRequest {
+    /// Protobuf encoded and snappy compressed, as sent over the wire.
+    pub fn encode_compressed(&self) -> Result<Vec<u8>> {
+        Ok(snap::raw::Encoder::new().compress_vec(&self.encode_to_vec())?)
+    }
+
+    pub fn decode_compressed(body: &[u8]) -> Result<WriteRequest> {
+        let decompressed = snap::raw::Decoder::new()
+            .decompress_vec(body)
+            .map_err(|e| anyhow!("Unable to decompress snappy body: {}", e))?;
+        WriteRequest::decode(decompressed.as_slice())
+            .map_err(|e| anyhow!("Unable to decode protobuf body: {}", e))
+    }
+}
+
+/// Labels of a series as sent over remote write, sorted by name.
+///
+/// `__name__` is made a valid metric name the same way the Prometheus text
+/// outputs do, but other label names are not rewritten: an invalid one, or
+/// one starting with the reserved `__`, is an error.
+pub fn labels(victoriametric: &VictoriaMetric) -> Result<Vec<Label>> {
+    let mut labels = vec![];
+    for (name, value) in victoriametric.labels().iter() {
+        let (name, value) = (name.clone(), value.clone());
+        if name == "__name__" {
+            labels.push(Label {
+                name,
+                value: metric_name(&value),
+            });
+            continue;
+        }
+        if !is_valid_label_name(&name) || name.starts_with("__") {
+            bail!(
+                "label {} of metric {} is not a valid Prometheus label name",
+                name,
+                victoriametric.metric
+            );
+        }
+        labels.push(Label { name, value });
+    }
+    labels.sort();
+    Ok(labels)
+}
+
+/// Splits the series into requests, one per `days` long time range, oldest
+/// first. Receivers usually reject samples that are older than what they
+/// already have for a series, so sending in time order lets a backfill of
+/// several years go through.
+pub fn batches(victoriametrics: &VictoriaMetrics, days: u64) -> Result<Vec<WriteRequest>> {
+    let window = days.max(1) * MS_PER_DAY;
+    let mut batches = BTreeMap::<u64, WriteRequest>::new();
+    let mut seen = HashSet::new();
+    for victoriametric in victoriametrics {
+        victoriametric.validate()?;
+        let labels = labels(victoriametric)?;
+        if !seen.insert(labels.clone()) {
+            bail!(
+                "metric {} is the same series as another one once sanitised for Prometheus",
+                victoriametric.metric
+            );
+        }
+
+        let mut series = BTreeMap::<u64, Vec<Sample>>::new();
+        for (&tstamp, &value) in victoriametric
+            .timestamps
+            .iter()
+            .zip(victoriametric.values.iter())
+        {
+            series.entry(tstamp / window).or_default().push(Sample {
+                value,
+                timestamp: i64::try_from(tstamp)
+                    .map_err(|_| anyhow!("timestamp {} is out of range", tstamp))?,
+            });
+        }
+        for (range, samples) in series {
+            batches
+                .entry(range)
+                .or_default()
+                .timeseries
+                .push(TimeSeries {
+                    labels: labels.clone(),
+                    samples,
+                });
+        }
+    }
+    Ok(batches.into_values().collect())
+}
+
+/// Sending metrics over the Prometheus remote write protocol, e.g. to Mimir,
+/// Thanos receive or VictoriaMetrics.
+#[derive(clap::Args, Deserialize, Debug, Clone, Default)]
+#[group(id = "remote_write")]
+#[command(next_help_heading = "Prometheus remote write")]
+#[serde(default, deny_unknown_fields)]
+pub struct Args {
+    /// Remote write endpoint to send metrics to, e.g. http://localhost:9090/api/v1/write
+    #[clap(id = "remote_write_url", long = "remote-write-url")]
+    pub url: Option<String>,
+
+    /// User for basic auth against the remote write endpoint
+    #[clap(id = "remote_write_user", long = "remote-write-user")]
+    pub user: Option<String>,
+
+    /// Password for basic auth against the remote write endpoint
+    #[clap(id = "remote_write_password", long = "remote-write-password")]
+    pub password: Option<String>,
+
+    /// Bearer token for the remote write endpoint
+    #[clap(id = "remote_write_token", long = "remote-write-token")]
+    pub token: Option<String>,
+
+    /// Days of samples sent per request, oldest first [default: 30]
+    #[clap(id = "remote_write_batch_days", long = "remote-write-batch-days")]
+    pub batch_days: Option<u64>,
+
+    /// How often a failed request is retried [default: 3]
+    #[clap(id = "remote_write_retries", long = "remote-write-retries")]
+    pub retries: Option<u32>,
+}
+
+impl Args {
+    /// Fills every argument not given here from `defaults`.
+    pub fn with_defaults(&self, defaults: &Args) -> Args {
+        Args {
+            url: self.url.clone().or(defaults.url.clone()),
+            user: self.user.clone().or(defaults.user.clone()),
+            password: self.password.clone().or(defaults.password.clone()),
+            token: self.token.clone().or(defaults.token.clone()),
+            batch_days: self.batch_days.or(defaults.batch_days),
+            retries: self.retries.or(defaults.retries),
+        }
+    }
+
+    pub async fn write(&self, victoriametrics: &VictoriaMetrics) -> Result<()> {
+        let url = match &self.url {
+            Some(url) => url,
+            None => return Ok(()),
+        };
+        let batches = batches(
+            victoriametrics,
+            self.batch_days.unwrap_or(DEFAULT_BATCH_DAYS),
+        )?;
+
+        let client = Client::new();
+        let mut total = 0;
+        for batch in &batches {
+            let body = batch.encode_compressed()?;
+            super::send(
+                || {
+                    let request = client
+                        .post(url)
+                        .header("Content-Encoding", "snappy")
+                        .header("Content-Type", "application/x-protobuf")
+                        .header("X-Prometheus-Remote-Write-Version", "0.1.0")
+                        .header(
+                            "User-Agent",
+                            concat!("nixos-metrics/", env!("CARGO_PKG_VERSION")),
+                        )
+                        .body(body.clone());
+                    match (&self.user, &self.token) {
+                        (Some(user), _) => request.basic_auth(user, self.password.as_ref()),
+                        (None, Some(token)) => request.bearer_auth(token),
+                        (None, None) => request,
+                    }
+                },
+                self.retries.unwrap_or(DEFAULT_RETRIES),
+            )
+            .await
+            .map_err(|e| anyhow!("Failed remote writing to {}: {}", url, e))?;
+            total += batch
+                .timeseries
+                .iter()
+                .map(|series| series.samples.len())
+                .sum::<usize>();
+        }
+        info!(
+            "Sent {} samples in {} requests to {}",
+            total,
+            batches.len(),
+            url
+        );
+
+        Ok(())
+    }
+}
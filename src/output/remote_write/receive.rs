@@ -0,0 +1,215 @@
+use crate::output::remote_write::{labels, Label, WriteRequest};
+use crate::process::{is_valid_label_name, read_victoriametrics, Labels, VictoriaMetric};
+use anyhow::{anyhow, bail, Result};
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    routing::post,
+    Router,
+};
+use clap::Parser;
+use log::{error, info};
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, oneshot};
+
+type Series = BTreeMap<Vec<Label>, BTreeMap<u64, f64>>;
+
+#[derive(Parser, Debug, Clone, Default)]
+#[command(version, about, author, long_about = None)]
+pub struct Cli {
+    /// Address to receive remote writes on at `/api/v1/write` [default: 127.0.0.1:9201]
+    #[clap(long)]
+    listen: Option<SocketAddr>,
+
+    /// VictoriaMetrics JSON line files with exactly the samples expected to be
+    /// received; exits once all of them arrived and fails on any other sample
+    #[clap(long, value_parser = clap::value_parser!(PathBuf))]
+    expect: Vec<PathBuf>,
+}
+
+struct Receiver {
+    expected: Option<Series>,
+    /// (labels, timestamp) of every expected sample received so far
+    received: BTreeSet<(Vec<Label>, u64)>,
+    done: mpsc::UnboundedSender<Result<()>>,
+}
+
+type Shared = Arc<Mutex<Receiver>>;
+
+/// Decodes a request and checks it follows the protocol: valid and sorted
+/// label names with a `__name__`, and samples sorted by timestamp.
+fn decode(headers: &HeaderMap, body: &[u8]) -> Result<Series> {
+    for (name, expected) in [
+        (header::CONTENT_ENCODING, "snappy"),
+        (header::CONTENT_TYPE, "application/x-protobuf"),
+    ] {
+        let value = headers.get(&name).and_then(|value| value.to_str().ok());
+        if value != Some(expected) {
+            bail!("expected {} {}, got {:?}", name, expected, value);
+        }
+    }
+
+    let mut series = Series::new();
+    for timeseries in WriteRequest::decode_compressed(body)?.timeseries {
+        let names: Vec<_> = timeseries.labels.iter().map(|l| l.name.as_str()).collect();
+        if !names.contains(&"__name__") {
+            bail!("series {:?} has no __name__", names);
+        }
+        if let Some(name) = names.iter().find(|name| !is_valid_label_name(name)) {
+            bail!("series {:?} has invalid label name {}", names, name);
+        }
+        if names.windows(2).any(|pair| pair[0] >= pair[1]) {
+            bail!("labels of series {:?} are not sorted", names);
+        }
+        if timeseries
+            .samples
+            .windows(2)
+            .any(|pair| pair[0].timestamp >= pair[1].timestamp)
+        {
+            bail!("samples of series {:?} are not sorted", names);
+        }
+
+        let samples = series.entry(timeseries.labels).or_default();
+        for sample in timeseries.samples {
+            let tstamp = u64::try_from(sample.timestamp)
+                .map_err(|_| anyhow!("negative timestamp {}", sample.timestamp))?;
+            samples.insert(tstamp, sample.value);
+        }
+    }
+    Ok(series)
+}
+
+/// Checks received samples against the expected ones, remembering which
+/// arrived. Returns whether all expected samples have been received.
+fn check(receiver: &mut Receiver, series: &Series) -> Result<bool> {
+    let expected = match &receiver.expected {
+        Some(expected) => expected,
+        None => return Ok(false),
+    };
+    for (labels, samples) in series {
+        let expected_samples = expected
+            .get(labels)
+            .ok_or_else(|| anyhow!("unexpected series {:?}", labels))?;
+        for (&tstamp, &value) in samples {
+            match expected_samples.get(&tstamp) {
+                Some(&v) if v == value || (v.is_nan() && value.is_nan()) => {}
+                Some(&v) => bail!(
+                    "series {:?} at {}: expected {}, got {}",
+                    labels,
+                    tstamp,
+                    v,
+                    value
+                ),
+                None => bail!("unexpected sample of series {:?} at {}", labels, tstamp),
+            }
+            receiver.received.insert((labels.clone(), tstamp));
+        }
+    }
+    let total: usize = expected.values().map(|samples| samples.len()).sum();
+    Ok(receiver.received.len() == total)
+}
+
+async fn write_handler(
+    State(receiver): State<Shared>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> (StatusCode, String) {
+    let series = match decode(&headers, &body) {
+        Ok(series) => series,
+        Err(e) => {
+            error!("Invalid remote write: {:#}", e);
+            return (StatusCode::BAD_REQUEST, format!("{:#}\n", e));
+        }
+    };
+    for (labels, samples) in &series {
+        let metric: BTreeMap<_, _> = labels
+            .iter()
+            .map(|l| (l.name.clone(), l.value.clone()))
+            .collect();
+        let metric = match Labels::try_from(metric) {
+            Ok(metric) => metric,
+            Err(e) => {
+                error!("Unable to print received series {:?}: {}", labels, e);
+                continue;
+            }
+        };
+        let (timestamps, values) = samples.iter().unzip();
+        match serde_json::to_string(&VictoriaMetric {
+            metric,
+            values,
+            timestamps,
+        }) {
+            Ok(line) => println!("{}", line),
+            Err(e) => error!("Unable to print received series: {}", e),
+        }
+    }
+
+    let mut receiver = receiver.lock().expect("receiver lock poisoned");
+    match check(&mut receiver, &series) {
+        Ok(false) => (StatusCode::NO_CONTENT, String::new()),
+        Ok(true) => {
+            let _ = receiver.done.send(Ok(()));
+            (StatusCode::NO_CONTENT, String::new())
+        }
+        Err(e) => {
+            let response = (StatusCode::BAD_REQUEST, format!("{:#}\n", e));
+            let _ = receiver.done.send(Err(e));
+            response
+        }
+    }
+}
+
+pub async fn run(args: &Cli) -> Result<()> {
+    let expected = if args.expect.is_empty() {
+        None
+    } else {
+        let mut expected = Series::new();
+        for path in &args.expect {
+            for victoriametric in read_victoriametrics(path)? {
+                expected
+                    .entry(labels(&victoriametric)?)
+                    .or_default()
+                    .extend(
+                        victoriametric
+                            .timestamps
+                            .iter()
+                            .copied()
+                            .zip(victoriametric.values.iter().copied()),
+                    );
+            }
+        }
+        Some(expected)
+    };
+
+    let (done, mut finished) = mpsc::unbounded_channel();
+    let app = Router::new()
+        .route("/api/v1/write", post(write_handler))
+        .with_state(Arc::new(Mutex::new(Receiver {
+            expected,
+            received: BTreeSet::new(),
+            done,
+        })));
+    let listen = args
+        .listen
+        .unwrap_or_else(|| SocketAddr::from(([127, 0, 0, 1], 9201)));
+    let listener = tokio::net::TcpListener::bind(listen)
+        .await
+        .map_err(|e| anyhow!("Unable to listen on {}: {}", listen, e))?;
+    info!("Receiving remote writes on http://{}/api/v1/write", listen);
+
+    // finish answering the last request before exiting, the sender would
+    // retry it otherwise
+    let (result, outcome) = oneshot::channel();
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async move {
+            let _ = result.send(finished.recv().await.unwrap_or(Ok(())));
+        })
+        .await?;
+    outcome.await.unwrap_or(Ok(()))?;
+    info!("Received every expected sample");
+    Ok(())
+}
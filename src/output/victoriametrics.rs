@@ -0,0 +1,186 @@
+use crate::process::{Labels, VictoriaMetric, VictoriaMetrics};
+use anyhow::{anyhow, Result};
+use flate2::{write::GzEncoder, Compression};
+use log::info;
+use reqwest::{Client, RequestBuilder, Response};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+
+const DEFAULT_BATCH_SIZE: usize = 10000;
+const DEFAULT_RETRIES: u32 = 3;
+/// Number of series looked up per `/api/v1/export` request.
+const SELECTORS_PER_REQUEST: usize = 50;
+
+/// Pushing metrics into a single-node VictoriaMetrics.
+#[derive(clap::Args, Deserialize, Debug, Clone, Default)]
+#[group(id = "victoriametrics")]
+#[command(next_help_heading = "VictoriaMetrics")]
+#[serde(default, deny_unknown_fields)]
+pub struct Args {
+    /// Base URL of a VictoriaMetrics to push metrics to, e.g. http://localhost:8428
+    #[clap(id = "victoriametrics_url", long = "victoriametrics-url")]
+    pub url: Option<String>,
+
+    /// User for basic auth against VictoriaMetrics
+    #[clap(id = "victoriametrics_user", long = "victoriametrics-user")]
+    pub user: Option<String>,
+
+    /// Password for basic auth against VictoriaMetrics
+    #[clap(id = "victoriametrics_password", long = "victoriametrics-password")]
+    pub password: Option<String>,
+
+    /// Number of samples after which a request is sent, series are never split [default: 10000]
+    #[clap(id = "victoriametrics_batch_size", long = "victoriametrics-batch-size")]
+    pub batch_size: Option<usize>,
+
+    /// How often a failed request is retried [default: 3]
+    #[clap(id = "victoriametrics_retries", long = "victoriametrics-retries")]
+    pub retries: Option<u32>,
+
+    /// Push all samples, not only those newer than what VictoriaMetrics already has
+    #[clap(id = "victoriametrics_force", long = "victoriametrics-force")]
+    pub force: bool,
+}
+
+impl Args {
+    /// Fills every argument not given here from `defaults`.
+    pub fn with_defaults(&self, defaults: &Args) -> Args {
+        Args {
+            url: self.url.clone().or(defaults.url.clone()),
+            user: self.user.clone().or(defaults.user.clone()),
+            password: self.password.clone().or(defaults.password.clone()),
+            batch_size: self.batch_size.or(defaults.batch_size),
+            retries: self.retries.or(defaults.retries),
+            force: self.force || defaults.force,
+        }
+    }
+
+    fn auth(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.user {
+            Some(user) => request.basic_auth(user, self.password.as_ref()),
+            None => request,
+        }
+    }
+
+    async fn send(&self, request: impl Fn() -> RequestBuilder) -> Result<Response> {
+        super::send(
+            || self.auth(request()),
+            self.retries.unwrap_or(DEFAULT_RETRIES),
+        )
+        .await
+    }
+
+    /// Latest timestamp VictoriaMetrics has for each of the series.
+    async fn latest(
+        &self,
+        client: &Client,
+        url: &str,
+        series: &[&Labels],
+    ) -> Result<HashMap<Labels, u64>> {
+        let endpoint = format!("{}/api/v1/export", url);
+        let mut latest = HashMap::new();
+        for chunk in series.chunks(SELECTORS_PER_REQUEST) {
+            let query: Vec<_> = chunk
+                .iter()
+                .map(|labels| ("match[]", selector(labels)))
+                .collect();
+            let response = self
+                .send(|| client.get(&endpoint).query(&query))
+                .await
+                .map_err(|e| anyhow!("Failed querying {}: {}", endpoint, e))?;
+            let text = response.text().await?;
+            for line in text.lines().filter(|line| !line.trim().is_empty()) {
+                let exported: VictoriaMetric = serde_json::from_str(line)
+                    .map_err(|e| anyhow!("Unable to parse response of {}: {}", endpoint, e))?;
+                if let Some(&max) = exported.timestamps.iter().max() {
+                    let entry = latest.entry(exported.metric).or_insert(max);
+                    *entry = max.max(*entry);
+                }
+            }
+        }
+        Ok(latest)
+    }
+
+    pub async fn write(&self, victoriametrics: &VictoriaMetrics) -> Result<()> {
+        let url = match &self.url {
+            Some(url) => url.trim_end_matches('/'),
+            None => return Ok(()),
+        };
+        let client = Client::new();
+
+        let series: Vec<_> = victoriametrics
+            .iter()
+            .map(|victoriametric| victoriametric.labels())
+            .collect();
+        let latest = if self.force {
+            HashMap::new()
+        } else {
+            self.latest(&client, url, &series).await?
+        };
+
+        // only samples newer than what is already there, as JSON lines
+        let mut lines = vec![];
+        for (victoriametric, labels) in victoriametrics.iter().zip(&series) {
+            let newer_than = latest.get(*labels);
+            let (timestamps, values): (Vec<_>, Vec<_>) = victoriametric
+                .timestamps
+                .iter()
+                .zip(victoriametric.values.iter())
+                .filter(|(tstamp, _)| newer_than.is_none_or(|latest| *tstamp > latest))
+                .unzip();
+            if timestamps.is_empty() {
+                continue;
+            }
+            lines.push((
+                timestamps.len(),
+                serde_json::to_string(&VictoriaMetric {
+                    metric: (*labels).clone(),
+                    values,
+                    timestamps,
+                })?,
+            ));
+        }
+
+        let endpoint = format!("{}/api/v1/import", url);
+        let batch_size = self.batch_size.unwrap_or(DEFAULT_BATCH_SIZE);
+        let total: usize = lines.iter().map(|(samples, _)| samples).sum();
+        let mut lines = lines.into_iter().peekable();
+        while lines.peek().is_some() {
+            let mut encoder = GzEncoder::new(vec![], Compression::default());
+            let mut samples = 0;
+            while let Some((n, line)) = lines.next_if(|_| samples < batch_size) {
+                writeln!(encoder, "{}", line)?;
+                samples += n;
+            }
+            let body = encoder.finish()?;
+            self.send(|| {
+                client
+                    .post(&endpoint)
+                    .header("Content-Encoding", "gzip")
+                    .body(body.clone())
+            })
+            .await
+            .map_err(|e| anyhow!("Failed pushing to {}: {}", endpoint, e))?;
+        }
+        info!("Pushed {} new samples to {}", total, endpoint);
+
+        Ok(())
+    }
+}
+
+/// Series selector matching exactly the given labels, e.g.
+/// `{__name__="netlify.sources",source="github.com"}`.
+fn selector(labels: &BTreeMap<String, String>) -> String {
+    let matchers: Vec<_> = labels
+        .iter()
+        .map(|(name, value)| {
+            format!(
+                "{}=\"{}\"",
+                name,
+                value.replace('\\', "\\\\").replace('"', "\\\"")
+            )
+        })
+        .collect();
+    format!("{{{}}}", matchers.join(","))
+}
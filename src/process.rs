@@ -1,19 +1,46 @@
-use anyhow::{anyhow, Result};
-use num_traits::{NumCast, ToPrimitive};
+use anyhow::{anyhow, bail, Error, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use gaps::Interpolation;
+use num_traits::NumCast;
+use reconcile::{Conflict, Reconciliation};
 use serde::{Deserialize, Serialize};
-use serde_json::json;
-use std::collections::{BTreeMap, HashMap};
-use std::fmt::Debug;
+use series::Metadatas;
+use smooth::Smoothing;
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::fmt::{self, Debug};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub mod all;
+pub mod anomaly;
+pub mod compact;
+pub mod diff;
+pub mod forecast;
+pub mod gaps;
+pub mod growth;
+pub mod reconcile;
+pub mod replay;
+pub mod resample;
+pub mod series;
+pub mod smooth;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Line {
     pub label: String,
     pub x: Vec<f64>,
-    pub y: Vec<f64>,
+    /// `None` (`null` in JSON) where a sample is missing
+    pub y: Vec<Option<f64>>,
+    /// `x` of the points that were interpolated rather than scraped
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub interpolated: Vec<f64>,
 }
 
 pub type Graph = Vec<Line>;
-pub type Graphs = HashMap<String, Graph>;
+pub type Graphs = BTreeMap<String, Graph>;
+
+/// Timestamps of the missing samples of a line, by graph and line label.
+pub type Gaps = BTreeMap<String, BTreeMap<String, Vec<u64>>>;
 
 impl Line {
     pub fn try_new<K, V>(label: impl Into<String>, hm: &BTreeMap<K, V>) -> Result<Line>
@@ -22,7 +49,7 @@ impl Line {
         V: NumCast + Copy + Debug,
     {
         let (x, y) = hm
-            .into_iter()
+            .iter()
             .map(|(x, y)| {
                 let x = x.to_f64().ok_or(anyhow!("Failed casting {:?} to f64", x))?;
                 let y = y.to_f64().ok_or(anyhow!("Failed casting {:?} to f64", y))?;
@@ -30,48 +57,457 @@ impl Line {
             })
             .collect::<Result<Vec<_>>>()?
             .into_iter()
+            .map(|(x, y)| (x, Some(y)))
             .unzip();
 
         Ok(Line {
             label: label.into(),
             x,
             y,
+            interpolated: vec![],
         })
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// `__name__` and labels of a series, e.g. `{"__name__": "netlify.sources",
+/// "source": "github.com"}`, which always has a `__name__`.
+///
+/// Metric names start with a letter, `_` or `:` followed by letters, digits,
+/// `_`, `:` or `.`, label names with a letter or `_` followed by letters,
+/// digits or `_`. Values may not be empty, as an empty label is the same as
+/// none to Prometheus and VictoriaMetrics.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(try_from = "BTreeMap<String, String>")]
+pub struct Labels(BTreeMap<String, String>);
+
+fn is_valid_metric_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_' || c == ':')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':' || c == '.')
+}
+
+pub(crate) fn is_valid_label_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+impl Labels {
+    /// Labels of the metric `name`, without any other label yet.
+    pub fn new(name: impl Into<String>) -> Result<Labels> {
+        Labels(BTreeMap::new()).with("__name__", name)
+    }
+
+    /// Adds the label `name` with `value`, replacing any value it had.
+    pub fn with(mut self, name: impl Into<String>, value: impl Into<String>) -> Result<Labels> {
+        let (name, value) = (name.into(), value.into());
+        if name == "__name__" {
+            if !is_valid_metric_name(&value) {
+                bail!("invalid metric name {:?}", value);
+            }
+        } else if !is_valid_label_name(&name) {
+            bail!("invalid label name {:?}", name);
+        } else if value.is_empty() {
+            bail!("label {} is empty", name);
+        }
+        self.0.insert(name, value);
+        Ok(self)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.0["__name__"]
+    }
+
+    /// Labels other than `__name__`.
+    pub fn labels(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.0
+            .iter()
+            .filter(|(name, _)| name.as_str() != "__name__")
+    }
+}
+
+impl std::ops::Deref for Labels {
+    type Target = BTreeMap<String, String>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl TryFrom<BTreeMap<String, String>> for Labels {
+    type Error = Error;
+
+    fn try_from(labels: BTreeMap<String, String>) -> Result<Labels> {
+        let name = labels
+            .get("__name__")
+            .ok_or_else(|| anyhow!("metric {:?} has no __name__", labels))?;
+        let mut checked = Labels::new(name)?;
+        for (name, value) in &labels {
+            if name != "__name__" {
+                checked = checked.with(name, value)?;
+            }
+        }
+        Ok(checked)
+    }
+}
+
+impl From<Labels> for BTreeMap<String, String> {
+    fn from(labels: Labels) -> Self {
+        labels.0
+    }
+}
+
+impl Serialize for Labels {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+/// Written like Prometheus does, e.g. `netlify.sources{source="github.com"}`.
+impl fmt::Display for Labels {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())?;
+        let labels: Vec<_> = self
+            .labels()
+            .map(|(name, value)| format!("{}={:?}", name, value))
+            .collect();
+        if !labels.is_empty() {
+            write!(f, "{{{}}}", labels.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+pub const MS_PER_DAY: u64 = 1000 * 60 * 60 * 24;
+
+/// Day of the timestamp `ms` in milliseconds, if it is in range.
+pub fn day_of(ms: u64) -> Option<NaiveDate> {
+    i64::try_from(ms)
+        .ok()
+        .and_then(DateTime::<Utc>::from_timestamp_millis)
+        .map(|datetime| datetime.date_naive())
+}
+
+/// Day of the timestamp `ms` in milliseconds, e.g. `2023-02-01`.
+pub fn to_date(ms: u64) -> String {
+    day_of(ms).map_or_else(|| ms.to_string(), |day| day.format("%Y-%m-%d").to_string())
+}
+
+/// A series in the VictoriaMetrics JSON line format.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(try_from = "UncheckedVictoriaMetric")]
 pub struct VictoriaMetric {
-    // `metric` must have `__name__` field. Everything else are labels
-    // TODO: Should we use a type that enforces this, and manually impl Serialize, Deserialize?
-    pub metric: serde_json::Value,
+    pub metric: Labels,
     pub values: Vec<f64>,
     pub timestamps: Vec<u64>,
 }
+
+/// A parsed record, before checking that it is a valid series.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UncheckedVictoriaMetric {
+    metric: Labels,
+    values: Vec<f64>,
+    timestamps: Vec<u64>,
+}
+
+impl TryFrom<UncheckedVictoriaMetric> for VictoriaMetric {
+    type Error = Error;
+
+    fn try_from(unchecked: UncheckedVictoriaMetric) -> Result<VictoriaMetric> {
+        let victoriametric = VictoriaMetric {
+            metric: unchecked.metric,
+            values: unchecked.values,
+            timestamps: unchecked.timestamps,
+        };
+        victoriametric.validate()?;
+        Ok(victoriametric)
+    }
+}
+
 pub type VictoriaMetrics = Vec<VictoriaMetric>;
 
+/// How a source turns its scraped data into series, and those into graphs
+/// beyond drawing them.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// What to do when snapshots disagree about a sample
+    pub reconciliation: Reconciliation,
+    pub smoothing: Vec<Smoothing>,
+    /// How missing samples are filled in, if at all
+    pub interpolation: Option<Interpolation>,
+}
+
+/// Everything a source produces when processing its scraped data.
+#[derive(Debug, Default)]
+pub struct Processed {
+    pub graphs: Graphs,
+    pub victoriametrics: VictoriaMetrics,
+    pub gaps: Gaps,
+    /// What the metrics in `victoriametrics` mean, by name
+    pub metadata: Metadatas,
+    /// Samples snapshots disagreed about, and the values taken
+    pub conflicts: Vec<Conflict>,
+}
+
 impl VictoriaMetric {
-    pub fn try_new(
-        name: impl Into<String>,
-        label: impl Into<String>,
-        line: &Line,
-    ) -> Result<VictoriaMetric> {
-        Ok(VictoriaMetric {
-            // TODO: there's got to be a cleaner way to write this
-            metric: match label.into().as_str() {
-                "" => json!({"__name__": name.into()}),
-                label => json!({
-                    "__name__": name.into(),
-                    label.to_owned(): line.label,
-                }),
-            },
-            values: line.y.clone(),
-            timestamps: line
-                .x
-                .clone()
-                .into_iter()
-                .map(|x| x.to_u64().ok_or(anyhow!("Filaed casting {:?} to u64", x)))
-                .collect::<Result<_>>()?,
+    /// `__name__` and labels of this metric, which together identify a series.
+    pub fn labels(&self) -> &Labels {
+        &self.metric
+    }
+
+    /// Checks that every value has a timestamp and that timestamps are
+    /// strictly increasing.
+    pub fn validate(&self) -> Result<()> {
+        if self.values.len() != self.timestamps.len() {
+            bail!(
+                "metric {} has {} values but {} timestamps",
+                self.metric,
+                self.values.len(),
+                self.timestamps.len()
+            );
+        }
+        if let Some(pair) = self.timestamps.windows(2).find(|pair| pair[0] >= pair[1]) {
+            bail!(
+                "metric {} has non-monotonic timestamps: {} followed by {}",
+                self.metric,
+                pair[0],
+                pair[1]
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Reads a file in VictoriaMetrics JSON line format, as written by
+/// `--victoriametrics-out`.
+pub fn read_victoriametrics(path: &Path) -> Result<VictoriaMetrics> {
+    let file_content = fs::read_to_string(path)
+        .map_err(|e| anyhow!("Unable to read file {}: {}", path.display(), e))?;
+    file_content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line).map_err(|e| {
+                anyhow!(
+                    "Unable to parse line {} of {}: {}",
+                    i + 1,
+                    path.display(),
+                    e
+                )
+            })
+        })
+        .collect()
+}
+
+/// Reads every file of `paths` in VictoriaMetrics JSON line format, and every
+/// `.jsonl` file in those that are directories, e.g. the `victoriametrics`
+/// directory of earlier runs.
+pub fn read_victoriametrics_paths(paths: &[PathBuf]) -> Result<VictoriaMetrics> {
+    let mut victoriametrics = vec![];
+    for path in paths {
+        if !path.is_dir() {
+            victoriametrics.extend(read_victoriametrics(path)?);
+            continue;
+        }
+        let mut files = vec![];
+        for entry in fs::read_dir(path)
+            .map_err(|e| anyhow!("Error listing directory {}: {}", path.display(), e))?
+        {
+            let file = entry
+                .map_err(|e| anyhow!("Error listing directory {}: {}", path.display(), e))?
+                .path();
+            if file
+                .extension()
+                .is_some_and(|extension| extension == "jsonl")
+            {
+                files.push(file);
+            }
+        }
+        // in a stable order, so errors are the same on every run
+        files.sort();
+        for file in files {
+            victoriametrics.extend(read_victoriametrics(&file)?);
+        }
+    }
+    Ok(victoriametrics)
+}
+
+/// Whether a series is one of the anomalies or growths [`derive`] adds,
+/// rather than scraped.
+pub fn is_derived(labels: &Labels) -> bool {
+    labels.name() == "anomaly" || labels.name().ends_with("_growth")
+}
+
+/// Adds the anomalies and growth of every series of `victoriametrics`, all
+/// sorted by series as by [`merge`].
+pub fn derive(mut victoriametrics: VictoriaMetrics) -> Result<VictoriaMetrics> {
+    let anomalies = anomaly::victoriametrics(&victoriametrics)?;
+    let growths = growth::victoriametrics(&victoriametrics)?;
+    victoriametrics.extend(anomalies);
+    victoriametrics.extend(growths);
+    merge(victoriametrics)
+}
+
+/// Merges records of the same series (same `__name__` and labels) into one,
+/// dropping duplicate samples and sorting them by timestamp. Two different
+/// values for the same timestamp of a series are an error.
+///
+/// The result is sorted by series so the output is the same on every run.
+pub fn merge(victoriametrics: impl IntoIterator<Item = VictoriaMetric>) -> Result<VictoriaMetrics> {
+    let mut series = BTreeMap::<Labels, BTreeMap<u64, f64>>::new();
+
+    for victoriametric in victoriametrics {
+        if victoriametric.values.len() != victoriametric.timestamps.len() {
+            bail!(
+                "metric {} has {} values but {} timestamps",
+                victoriametric.metric,
+                victoriametric.values.len(),
+                victoriametric.timestamps.len()
+            );
+        }
+        let samples = series.entry(victoriametric.metric.clone()).or_default();
+        for (&tstamp, &value) in victoriametric
+            .timestamps
+            .iter()
+            .zip(victoriametric.values.iter())
+        {
+            match samples.entry(tstamp) {
+                Entry::Vacant(entry) => {
+                    entry.insert(value);
+                }
+                Entry::Occupied(entry) => {
+                    let v = *entry.get();
+                    if v != value && !(v.is_nan() && value.is_nan()) {
+                        bail!(
+                            "data mismatch on {} for metric {}: {} and {}",
+                            tstamp,
+                            victoriametric.metric,
+                            v,
+                            value
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    series
+        .into_iter()
+        .map(|(labels, samples)| {
+            let (timestamps, values) = samples.into_iter().unzip();
+            let victoriametric = VictoriaMetric {
+                metric: labels,
+                values,
+                timestamps,
+            };
+            victoriametric.validate()?;
+            Ok(victoriametric)
         })
+        .collect()
+}
+
+/// Combines what several sources produced, failing if two of them produce a
+/// graph of the same name.
+pub fn combine(sources: impl IntoIterator<Item = Processed>) -> Result<Processed> {
+    let mut graphs = Graphs::new();
+    let mut victoriametrics = vec![];
+    let mut gaps = Gaps::new();
+    let mut metadata = Metadatas::new();
+    let mut conflicts = vec![];
+    for source in sources {
+        for (name, graph) in source.graphs {
+            if graphs.contains_key(&name) {
+                bail!("Graph {} is produced by more than one source", name);
+            }
+            graphs.insert(name, graph);
+        }
+        victoriametrics.extend(source.victoriametrics);
+        gaps.extend(source.gaps);
+        conflicts.extend(source.conflicts);
+        for (name, source_metadata) in source.metadata {
+            match metadata.get(&name) {
+                Some(existing) if *existing != source_metadata => {
+                    bail!("Metric {} is described differently by two sources", name)
+                }
+                _ => {
+                    metadata.insert(name, source_metadata);
+                }
+            }
+        }
+    }
+    Ok(Processed {
+        graphs,
+        victoriametrics: merge(victoriametrics)?,
+        gaps,
+        metadata,
+        conflicts,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_accept_valid_names() {
+        let labels = Labels::new("netlify.sources")
+            .and_then(|labels| labels.with("source", "github.com"))
+            .unwrap();
+        assert_eq!(labels.name(), "netlify.sources");
+        assert_eq!(
+            labels.to_string(),
+            r#"netlify.sources{source="github.com"}"#
+        );
+        assert_eq!(labels.labels().count(), 1);
+    }
+
+    #[test]
+    fn labels_reject_invalid_metric_names() {
+        for name in ["", "1pageviews", "page views", "pageviews-total"] {
+            assert!(Labels::new(name).is_err(), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn labels_reject_invalid_label_names_and_empty_values() {
+        let labels = Labels::new("gtrends").unwrap();
+        for name in ["", "1term", "search.term", "search-term"] {
+            assert!(labels.clone().with(name, "NixOS").is_err(), "{:?}", name);
+        }
+        assert!(labels.with("search_term", "").is_err());
+    }
+
+    #[test]
+    fn labels_need_a_name() {
+        let labels = BTreeMap::from([("source".to_owned(), "github.com".to_owned())]);
+        assert!(Labels::try_from(labels).is_err());
+    }
+
+    #[test]
+    fn records_are_checked_when_parsed() {
+        let valid = r#"{"metric":{"__name__":"gtrends","search_term":"NixOS"},"values":[1,2],"timestamps":[1000,2000]}"#;
+        let victoriametric: VictoriaMetric = serde_json::from_str(valid).unwrap();
+        assert_eq!(victoriametric.labels()["search_term"], "NixOS");
+
+        for invalid in [
+            // a value without timestamp
+            r#"{"metric":{"__name__":"gtrends"},"values":[1,2],"timestamps":[1000]}"#,
+            // timestamps going back
+            r#"{"metric":{"__name__":"gtrends"},"values":[1,2],"timestamps":[2000,1000]}"#,
+            // an empty label
+            r#"{"metric":{"__name__":"gtrends","search_term":""},"values":[1],"timestamps":[1000]}"#,
+            // no name
+            r#"{"metric":{"search_term":"NixOS"},"values":[1],"timestamps":[1000]}"#,
+            // an unknown field
+            r#"{"metric":{"__name__":"gtrends"},"values":[1],"timestamps":[1000],"unit":"%"}"#,
+        ] {
+            assert!(
+                serde_json::from_str::<VictoriaMetric>(invalid).is_err(),
+                "{}",
+                invalid
+            );
+        }
     }
 }
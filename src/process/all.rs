@@ -0,0 +1,74 @@
+use crate::{
+    config::Config,
+    gtrends, netlify,
+    output::Outputs,
+    process::{combine, Options},
+};
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use std::path::PathBuf;
+
+#[derive(Parser, Debug, Clone, Default)]
+#[command(version, about, author, long_about = None)]
+pub struct Cli {
+    /// Directory where the Netlify data has been collected
+    #[clap(long, value_parser = clap::value_parser!(PathBuf))]
+    netlify_dir: Option<PathBuf>,
+
+    /// File where the Google Trends data has been collected
+    #[clap(long, value_parser = clap::value_parser!(PathBuf))]
+    gtrends_data: Option<PathBuf>,
+
+    #[clap(skip)]
+    netlify_state: Option<PathBuf>,
+
+    #[clap(skip)]
+    netlify_options: Options,
+
+    #[clap(skip)]
+    gtrends_options: Options,
+
+    #[command(flatten)]
+    outputs: Outputs,
+}
+
+impl Cli {
+    /// Fills every argument not given on the command line from `config`.
+    pub fn with_config(&self, config: &Config) -> Cli {
+        let netlify_dir = self
+            .netlify_dir
+            .clone()
+            .or(config.sources.netlify.dir.clone());
+        Cli {
+            netlify_state: config.sources.netlify.state_of(netlify_dir.as_deref()),
+            netlify_dir,
+            gtrends_data: self
+                .gtrends_data
+                .clone()
+                .or(config.sources.gtrends.data.clone()),
+            netlify_options: config.sources.netlify.options(),
+            gtrends_options: config.sources.gtrends.options(),
+            outputs: self.outputs.with_defaults(&config.outputs),
+        }
+    }
+}
+
+pub async fn run(args: &Cli) -> Result<()> {
+    let netlify_dir = args.netlify_dir.as_deref().ok_or(anyhow!(
+        "No Netlify data, pass --netlify-dir or set sources.netlify.dir in the config"
+    ))?;
+    let gtrends_data = args.gtrends_data.as_deref().ok_or(anyhow!(
+        "No Google Trends data, pass --gtrends-data or set sources.gtrends.data in the config"
+    ))?;
+
+    let processed = combine([
+        netlify::process::process_dir_cached(
+            netlify_dir,
+            args.netlify_state.as_deref(),
+            false,
+            &args.netlify_options,
+        )?,
+        gtrends::process::process_file(gtrends_data, &args.gtrends_options)?,
+    ])?;
+    args.outputs.write(&processed).await
+}
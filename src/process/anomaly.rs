@@ -0,0 +1,320 @@
+use crate::process::{
+    resample::resolution, series::Metadata, Labels, Line, VictoriaMetric, VictoriaMetrics,
+    MS_PER_DAY,
+};
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Datelike, Utc};
+use num_traits::ToPrimitive;
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+
+/// Robust z-score above which a sample, or a change of level, is anomalous.
+/// 3.5 is the usual cut-off for the MAD based z-score.
+const THRESHOLD: f64 = 3.5;
+
+/// Scales the median absolute deviation to the standard deviation of
+/// normally distributed data.
+const MAD_SCALE: f64 = 1.4826;
+
+/// What makes a sample anomalous.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Kind {
+    /// A single sample far off from its neighbours, e.g. a spike from a
+    /// Hacker News post
+    Outlier,
+    /// The first sample of a lasting change of level, e.g. a drop after a
+    /// broken deploy
+    LevelShift,
+}
+
+impl Kind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Kind::Outlier => "outlier",
+            Kind::LevelShift => "level_shift",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Anomaly {
+    pub kind: Kind,
+    /// Value of the sample
+    pub value: f64,
+    /// How far off the sample (or the new level) is, in robust standard
+    /// deviations of the residuals: positive for spikes and rises, negative
+    /// for drops
+    pub score: f64,
+}
+
+fn median(values: &[f64]) -> Option<f64> {
+    let mut values = values.to_vec();
+    values.sort_by(f64::total_cmp);
+    let middle = values.len() / 2;
+    match values.len() {
+        0 => None,
+        n if n % 2 == 0 => Some((values[middle - 1] + values[middle]) / 2.0),
+        _ => Some(values[middle]),
+    }
+}
+
+fn weekday(ms: u64) -> Option<usize> {
+    let datetime = DateTime::<Utc>::from_timestamp_millis(i64::try_from(ms).ok()?)?;
+    Some(datetime.weekday().num_days_from_monday() as usize)
+}
+
+/// Finds the anomalies of `series` (timestamps in milliseconds), keyed by
+/// timestamp and kind, as a sample can be both an outlier and where a level
+/// shift starts.
+///
+/// The trend, a centred rolling median, is taken out first, then for daily
+/// series the median residual of every weekday, so quiet weekends are not
+/// flagged. Samples whose remaining residual has a robust z-score (based on
+/// the median absolute deviation) beyond 3.5 are outliers. A level shift is
+/// where the means of the windows before and after a sample, outliers
+/// clipped, differ by more than that from the typical difference, which
+/// accounts for the overall trend.
+///
+/// Series too short to tell what is normal have no anomalies.
+pub fn detect<K, V>(series: &BTreeMap<K, V>) -> Result<BTreeMap<(u64, Kind), Anomaly>>
+where
+    K: ToPrimitive + Copy + Debug,
+    V: ToPrimitive + Copy + Debug,
+{
+    let (timestamps, values): (Vec<u64>, Vec<f64>) = series
+        .iter()
+        .map(|(x, y)| {
+            let x = x.to_u64().ok_or(anyhow!("Failed casting {:?} to u64", x))?;
+            let y = y.to_f64().ok_or(anyhow!("Failed casting {:?} to f64", y))?;
+            Ok((x, y))
+        })
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .filter(|(_, y)| y.is_finite())
+        .unzip();
+
+    let daily = resolution(&timestamps) == Some(MS_PER_DAY);
+    // samples in the trend window and in each window around a level shift;
+    // whole weeks for daily series, so the weekdays even out
+    let (trend_window, shift_window) = if daily { (7, 14) } else { (5, 6) };
+    let mut anomalies = BTreeMap::new();
+    if values.len() < 2 * shift_window {
+        return Ok(anomalies);
+    }
+
+    let half = trend_window / 2;
+    let detrended: Vec<f64> = (0..values.len())
+        .map(|i| {
+            let window = &values[i.saturating_sub(half)..(i + half + 1).min(values.len())];
+            values[i] - median(window).expect("windows are never empty")
+        })
+        .collect();
+
+    let mut residuals = detrended.clone();
+    if daily {
+        let mut by_weekday: [Vec<f64>; 7] = Default::default();
+        for (tstamp, value) in timestamps.iter().zip(&detrended) {
+            if let Some(day) = weekday(*tstamp) {
+                by_weekday[day].push(*value);
+            }
+        }
+        let seasonal = by_weekday.map(|values| median(&values).unwrap_or(0.0));
+        for (tstamp, residual) in timestamps.iter().zip(residuals.iter_mut()) {
+            if let Some(day) = weekday(*tstamp) {
+                *residual -= seasonal[day];
+            }
+        }
+    }
+
+    let centre = median(&residuals).expect("series is not empty");
+    let deviations: Vec<f64> = residuals.iter().map(|r| (r - centre).abs()).collect();
+    let sigma = MAD_SCALE * median(&deviations).expect("series is not empty");
+    // a series that is mostly constant or perfectly regular has no noise to
+    // compare with
+    if sigma <= 0.0 {
+        return Ok(anomalies);
+    }
+
+    for (i, residual) in residuals.iter().enumerate() {
+        let score = (residual - centre) / sigma;
+        if score.abs() > THRESHOLD {
+            anomalies.insert(
+                (timestamps[i], Kind::Outlier),
+                Anomaly {
+                    kind: Kind::Outlier,
+                    value: values[i],
+                    score,
+                },
+            );
+        }
+    }
+
+    // outliers clipped to the threshold, so they do not show as shifts; means
+    // rather than medians, as their differences peak right at a shift
+    let clipped: Vec<f64> = values
+        .iter()
+        .zip(&residuals)
+        .map(|(value, residual)| {
+            let deviation = residual - centre;
+            value - deviation + deviation.clamp(-THRESHOLD * sigma, THRESHOLD * sigma)
+        })
+        .collect();
+    let mean = |values: &[f64]| values.iter().sum::<f64>() / values.len() as f64;
+    // the change between consecutive windows that the overall trend makes
+    // typical, so steady growth is not a shift
+    let changes: Vec<Option<f64>> = (0..values.len())
+        .map(|i| {
+            if i < shift_window || i + shift_window > values.len() {
+                return None;
+            }
+            let before = mean(&clipped[i - shift_window..i]);
+            let after = mean(&clipped[i..i + shift_window]);
+            Some(after - before)
+        })
+        .collect();
+    let typical = median(&changes.iter().flatten().copied().collect::<Vec<_>>()).unwrap_or(0.0);
+    let shifts: Vec<Option<f64>> = changes
+        .iter()
+        .map(|change| change.map(|change| (change - typical) / sigma))
+        .collect();
+    for (i, score) in shifts.iter().enumerate() {
+        let Some(score) = *score else { continue };
+        // only the sample where the shift is most pronounced
+        let strongest = shifts
+            .iter()
+            .enumerate()
+            .skip(i.saturating_sub(shift_window))
+            .take(2 * shift_window + 1)
+            .all(|(j, other)| match other {
+                Some(other) if j < i => other.abs() < score.abs(),
+                Some(other) => other.abs() <= score.abs(),
+                None => true,
+            });
+        if score.abs() > THRESHOLD && strongest {
+            anomalies.insert(
+                (timestamps[i], Kind::LevelShift),
+                Anomaly {
+                    kind: Kind::LevelShift,
+                    value: values[i],
+                    score,
+                },
+            );
+        }
+    }
+
+    Ok(anomalies)
+}
+
+/// Metadata of the `anomaly` metric, whatever the series the anomalies are
+/// found in.
+pub fn metadata() -> Metadata {
+    Metadata {
+        unit: "score".to_owned(),
+        description: "Anomalies found in the metric of the metric label, \
+            valued with how many robust standard deviations they are off"
+            .to_owned(),
+        resolution: None,
+    }
+}
+
+/// Anomaly markers for `series` by label, as a line per series with
+/// anomalies, placed on the values of the anomalous samples. With a single
+/// series the line is labeled `Anomalies`, otherwise `SERIES anomalies`.
+pub fn lines<K, V>(series: &[(String, &BTreeMap<K, V>)]) -> Result<Vec<Line>>
+where
+    K: ToPrimitive + Copy + Debug,
+    V: ToPrimitive + Copy + Debug,
+{
+    let mut lines = vec![];
+    for (label, data) in series {
+        let anomalies = detect(*data)?;
+        if anomalies.is_empty() {
+            continue;
+        }
+        let label = match series.len() {
+            1 => "Anomalies".to_owned(),
+            _ => format!("{} anomalies", label),
+        };
+        lines.push(Line::try_new(
+            label,
+            &anomalies
+                .into_iter()
+                .map(|((x, _), anomaly)| (x, anomaly.value))
+                .collect(),
+        )?);
+    }
+    Ok(lines)
+}
+
+/// An `anomaly` series for every kind of anomaly found in each of
+/// `victoriametrics`, labeled with the `metric` it was found in, the labels
+/// of that metric and its `kind`. Values are the scores of the anomalies.
+pub fn victoriametrics(victoriametrics: &[VictoriaMetric]) -> Result<VictoriaMetrics> {
+    let mut anomalies = vec![];
+    for victoriametric in victoriametrics {
+        let labels = victoriametric.labels();
+        for label in ["metric", "kind"] {
+            if labels.contains_key(label) {
+                bail!(
+                    "metric {} has a {} label, which anomalies use themselves",
+                    victoriametric.metric,
+                    label
+                );
+            }
+        }
+
+        let series: BTreeMap<u64, f64> = victoriametric
+            .timestamps
+            .iter()
+            .copied()
+            .zip(victoriametric.values.iter().copied())
+            .collect();
+        let found = detect(&series)?;
+        for kind in [Kind::Outlier, Kind::LevelShift] {
+            let (timestamps, values): (Vec<u64>, Vec<f64>) = found
+                .iter()
+                .filter(|(_, anomaly)| anomaly.kind == kind)
+                .map(|((tstamp, _), anomaly)| (*tstamp, anomaly.score))
+                .unzip();
+            if timestamps.is_empty() {
+                continue;
+            }
+            let mut metric = Labels::new("anomaly")?
+                .with("metric", labels.name())?
+                .with("kind", kind.as_str())?;
+            for (name, value) in labels.labels() {
+                metric = metric.with(name, value)?;
+            }
+            anomalies.push(VictoriaMetric {
+                metric,
+                values,
+                timestamps,
+            });
+        }
+    }
+    Ok(anomalies)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn an_outlier_can_start_a_level_shift() {
+        // a spike right where the level doubles
+        let series: BTreeMap<u64, f64> = (0..40u64)
+            .map(|i| {
+                let noise = (i * 7 % 5) as f64;
+                let value = match i {
+                    0..20 => 100.0,
+                    20 => 400.0,
+                    _ => 200.0,
+                };
+                (i, value + noise)
+            })
+            .collect();
+        let anomalies = detect(&series).unwrap();
+        assert_eq!(anomalies[&(20, Kind::Outlier)].value, 400.0);
+        assert_eq!(anomalies[&(20, Kind::LevelShift)].value, 400.0);
+    }
+}
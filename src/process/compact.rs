@@ -0,0 +1,34 @@
+use crate::{
+    output::write_victoriametrics,
+    process::{merge, read_victoriametrics},
+};
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use std::fs;
+use std::path::PathBuf;
+
+#[derive(Parser, Debug, Clone)]
+#[command(version, about, author, long_about = None)]
+pub struct Cli {
+    /// VictoriaMetrics JSON line file, e.g. with records appended by --victoriametrics-append
+    #[clap(value_parser = clap::value_parser!(PathBuf))]
+    file: PathBuf,
+
+    /// Write the compacted records here instead of replacing the file
+    #[clap(long, value_parser = clap::value_parser!(PathBuf))]
+    out: Option<PathBuf>,
+}
+
+/// Folds the records of every series into one, sorted by series like a full
+/// write, so appended deltas do not pile up.
+pub async fn run(args: &Cli) -> Result<()> {
+    let victoriametrics = merge(read_victoriametrics(&args.file)?)?;
+    let out = args.out.as_ref().unwrap_or(&args.file);
+
+    // replacing the file only once it is complete, so a failure never loses
+    // the records
+    let tmp = out.with_extension("jsonl.tmp");
+    write_victoriametrics(&tmp, &victoriametrics)?;
+    fs::rename(&tmp, out).map_err(|e| anyhow!("Unable to write file {}: {}", out.display(), e))?;
+    Ok(())
+}
@@ -0,0 +1,138 @@
+use crate::process::{
+    series::{self, Metadatas, Series},
+    Labels,
+};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+type Samples = BTreeMap<DateTime<Utc>, f64>;
+
+#[derive(Parser, Debug, Clone)]
+#[command(version, about, author, long_about = None)]
+pub struct Cli {
+    /// VictoriaMetrics JSON line file of the earlier run, or a directory of them
+    #[clap(value_parser = clap::value_parser!(PathBuf))]
+    old: PathBuf,
+
+    /// VictoriaMetrics JSON line file of the later run, or a directory of them
+    #[clap(value_parser = clap::value_parser!(PathBuf))]
+    new: PathBuf,
+}
+
+/// How a series differs between two runs.
+#[derive(Debug, PartialEq)]
+pub enum Change {
+    /// Only in the later run, with its number of samples
+    Added(usize),
+    /// Only in the earlier run, with its number of samples
+    Removed(usize),
+    /// In both runs, with samples only in the later one, only in the earlier
+    /// one and in both with different values
+    Changed {
+        added: Vec<DateTime<Utc>>,
+        removed: Vec<DateTime<Utc>>,
+        changed: Vec<DateTime<Utc>>,
+    },
+}
+
+/// Changes from the series `old` to `new` by series, leaving out the ones
+/// that are the same.
+pub fn diff(old: &[Series], new: &[Series]) -> BTreeMap<Labels, Change> {
+    fn by_metric(series: &[Series]) -> BTreeMap<&Labels, &Samples> {
+        series
+            .iter()
+            .map(|series| (&series.labels, &series.samples))
+            .collect()
+    }
+    let (old, new) = (by_metric(old), by_metric(new));
+
+    let mut changes = BTreeMap::new();
+    for (metric, samples) in &old {
+        if !new.contains_key(metric) {
+            changes.insert((*metric).clone(), Change::Removed(samples.len()));
+        }
+    }
+    for (metric, samples) in new {
+        let Some(old_samples) = old.get(&metric) else {
+            changes.insert(metric.clone(), Change::Added(samples.len()));
+            continue;
+        };
+        let added: Vec<_> = samples
+            .keys()
+            .filter(|tstamp| !old_samples.contains_key(tstamp))
+            .copied()
+            .collect();
+        let removed: Vec<_> = old_samples
+            .keys()
+            .filter(|tstamp| !samples.contains_key(tstamp))
+            .copied()
+            .collect();
+        let changed: Vec<_> = samples
+            .iter()
+            .filter(|(tstamp, value)| {
+                old_samples.get(tstamp).is_some_and(|old_value| {
+                    old_value != *value && !(old_value.is_nan() && value.is_nan())
+                })
+            })
+            .map(|(tstamp, _)| *tstamp)
+            .collect();
+        if !(added.is_empty() && removed.is_empty() && changed.is_empty()) {
+            changes.insert(
+                metric.clone(),
+                Change::Changed {
+                    added,
+                    removed,
+                    changed,
+                },
+            );
+        }
+    }
+    changes
+}
+
+/// Prints a line for every series that differs between the two runs: `+` for
+/// added series, `-` for removed ones and `~` for ones with different
+/// samples, followed by the days of those samples.
+pub async fn run(args: &Cli) -> Result<()> {
+    // only the samples are compared, which need no description
+    let old = series::read(std::slice::from_ref(&args.old), &Metadatas::new())?;
+    let new = series::read(std::slice::from_ref(&args.new), &Metadatas::new())?;
+
+    let days = |tstamps: &[DateTime<Utc>]| {
+        tstamps
+            .iter()
+            .map(|tstamp| tstamp.format("%Y-%m-%d").to_string())
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    for (metric, change) in diff(&old, &new) {
+        match change {
+            Change::Added(samples) => println!("+ {} ({} samples)", metric, samples),
+            Change::Removed(samples) => println!("- {} ({} samples)", metric, samples),
+            Change::Changed {
+                added,
+                removed,
+                changed,
+            } => {
+                println!(
+                    "~ {} ({} added, {} removed, {} changed samples)",
+                    metric,
+                    added.len(),
+                    removed.len(),
+                    changed.len()
+                );
+                for (kind, tstamps) in
+                    [("added", added), ("removed", removed), ("changed", changed)]
+                {
+                    if !tstamps.is_empty() {
+                        println!("    {}: {}", kind, days(&tstamps));
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,278 @@
+use crate::process::{
+    gaps::{self, Interpolation},
+    Line, MS_PER_DAY,
+};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Months, Utc};
+use num_traits::ToPrimitive;
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+
+/// Quantile of the normal distribution for 95% prediction bands.
+const Z_95: f64 = 1.96;
+
+/// Smoothing factors of the level and seasonality tried when fitting a
+/// model.
+const FACTORS: [f64; 9] = [0.1, 0.2, 0.3, 0.4, 0.5, 0.6, 0.7, 0.8, 0.9];
+
+/// Smoothing factors of the trend tried when fitting a model, kept low so a
+/// single jump does not turn into a steep trend.
+const TREND_FACTORS: [f64; 5] = [0.01, 0.05, 0.1, 0.2, 0.3];
+
+/// Dampings of the trend tried when fitting a model. Trends flatten out
+/// rather than go on forever, which keeps long projections sensible.
+const DAMPINGS: [f64; 3] = [0.8, 0.9, 0.98];
+
+/// How a series is projected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Model {
+    /// Additive Holt-Winters, a level, damped trend and seasonal component
+    /// of `season` samples, e.g. 7 for the weekly cycle of daily data
+    HoltWinters { season: usize },
+    /// Holt's linear trend, a level and damped trend without seasonality
+    Holt,
+}
+
+/// How far ahead a series is projected, at the resolution of the series.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Horizon {
+    Days(u64),
+    /// Calendar months, so monthly data keeps falling on the same day
+    Months(u32),
+}
+
+impl Horizon {
+    /// Timestamps of the projected samples after `last`.
+    fn timestamps(&self, last: u64) -> Result<Vec<u64>> {
+        match *self {
+            Horizon::Days(days) => Ok((1..=days).map(|day| last + day * MS_PER_DAY).collect()),
+            Horizon::Months(months) => {
+                let last = i64::try_from(last)
+                    .ok()
+                    .and_then(DateTime::<Utc>::from_timestamp_millis)
+                    .ok_or_else(|| anyhow!("timestamp {} is out of range", last))?;
+                (1..=months)
+                    .map(|month| {
+                        last.checked_add_months(Months::new(month))
+                            .map(|datetime| datetime.timestamp_millis() as u64)
+                            .ok_or_else(|| {
+                                anyhow!("{} months after {} is out of range", month, last)
+                            })
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Projected values of a series, keyed by timestamp, with the bounds of
+/// their 95% prediction interval.
+#[derive(Debug, Default)]
+pub struct Forecast {
+    pub predicted: BTreeMap<u64, f64>,
+    pub lower: BTreeMap<u64, f64>,
+    pub upper: BTreeMap<u64, f64>,
+}
+
+/// A fitted model: its state after the last sample and the variance of its
+/// one step ahead errors.
+struct Fit {
+    level: f64,
+    trend: f64,
+    /// Seasonal components, the one of the next sample first
+    seasonal: Vec<f64>,
+    alpha: f64,
+    beta: f64,
+    gamma: f64,
+    phi: f64,
+    variance: f64,
+}
+
+/// Runs the model with the given factors over `values`, which must cover at
+/// least two seasons.
+fn fit(values: &[f64], season: usize, alpha: f64, beta: f64, gamma: f64, phi: f64) -> Fit {
+    // the first season sets the level and the seasonal components, the
+    // second the trend
+    let (mut level, mut trend, mut seasonal) = if season > 1 {
+        let first = values[..season].iter().sum::<f64>() / season as f64;
+        let second = values[season..2 * season].iter().sum::<f64>() / season as f64;
+        let seasonal: Vec<f64> = values[..season].iter().map(|value| value - first).collect();
+        (first, (second - first) / season as f64, seasonal)
+    } else {
+        (values[0], values[1] - values[0], vec![0.0])
+    };
+    // the level and trend describe the middle of the first season, step them
+    // to its end
+    let start = season.max(1);
+    if season > 1 {
+        level += trend * (season - 1) as f64 / 2.0;
+    }
+
+    let mut squared_errors = 0.0;
+    for (i, value) in values.iter().enumerate().skip(start) {
+        let s = seasonal[i % season.max(1)];
+        let error = value - (level + phi * trend + s);
+        squared_errors += error * error;
+        let previous = level;
+        level = alpha * (value - s) + (1.0 - alpha) * (level + phi * trend);
+        trend = beta * (level - previous) + (1.0 - beta) * phi * trend;
+        if season > 1 {
+            seasonal[i % season] = gamma * (value - level) + (1.0 - gamma) * s;
+        }
+    }
+    seasonal.rotate_left(values.len() % season.max(1));
+
+    Fit {
+        level,
+        trend,
+        seasonal,
+        alpha,
+        beta,
+        gamma,
+        phi,
+        variance: squared_errors / (values.len() - start) as f64,
+    }
+}
+
+/// Projects `series` (timestamps in milliseconds) over `horizon`, fitting
+/// the smoothing factors of `model` to the ones with the smallest one step
+/// ahead errors.
+///
+/// The prediction bands widen with the distance, following the variance of
+/// the errors of the model. Values are counts or relative interest, so
+/// neither the projection nor its bands go below 0.
+///
+/// Missing samples are interpolated linearly before fitting. Series too
+/// short for the model, less than two seasons or three samples, have no
+/// forecast.
+pub fn forecast<K, V>(series: &BTreeMap<K, V>, model: Model, horizon: Horizon) -> Result<Forecast>
+where
+    K: ToPrimitive + Copy + Debug,
+    V: ToPrimitive + Copy + Debug,
+{
+    let mut samples = series
+        .iter()
+        .map(|(x, y)| {
+            let x = x.to_u64().ok_or(anyhow!("Failed casting {:?} to u64", x))?;
+            let y = y.to_f64().ok_or(anyhow!("Failed casting {:?} to f64", y))?;
+            Ok((x, y))
+        })
+        .collect::<Result<BTreeMap<_, _>>>()?;
+    // the season of a sample is its position, so missing samples are filled
+    // in for the ones after them to stay in their place in the season
+    let filled: Vec<_> = gaps::missing(&samples.keys().copied().collect::<Vec<_>>())?
+        .into_iter()
+        .filter_map(|x| Some((x, gaps::interpolate(&samples, x, Interpolation::Linear)?)))
+        .collect();
+    samples.extend(filled);
+    let (timestamps, values): (Vec<u64>, Vec<f64>) = samples.into_iter().unzip();
+
+    let season = match model {
+        Model::HoltWinters { season } => season,
+        Model::Holt => 1,
+    };
+    let Some(&last) = timestamps.last() else {
+        return Ok(Forecast::default());
+    };
+    if values.len() < (2 * season).max(3) {
+        return Ok(Forecast::default());
+    }
+
+    let gammas: &[f64] = if season > 1 { &FACTORS } else { &[0.0] };
+    let mut best: Option<Fit> = None;
+    for &alpha in &FACTORS {
+        for &beta in &TREND_FACTORS {
+            for &gamma in gammas {
+                for &phi in &DAMPINGS {
+                    let fitted = fit(&values, season, alpha, beta, gamma, phi);
+                    if best
+                        .as_ref()
+                        .is_none_or(|best| fitted.variance < best.variance)
+                    {
+                        best = Some(fitted);
+                    }
+                }
+            }
+        }
+    }
+    let fit = best.expect("at least one model is fitted");
+
+    let mut forecast = Forecast::default();
+    // variance of the h step ahead error of additive Holt-Winters, growing by
+    // how much each earlier error carries over
+    let mut spread = 1.0;
+    // phi + phi^2 + ... + phi^h, how much of the trend adds up after h steps
+    let mut damped = 0.0;
+    for (h, tstamp) in horizon.timestamps(last)?.into_iter().enumerate() {
+        let h = h + 1;
+        damped += fit.phi.powi(h as i32);
+        let predicted = fit.level + damped * fit.trend + fit.seasonal[(h - 1) % season];
+        let margin = Z_95 * (fit.variance * spread).sqrt();
+        forecast.predicted.insert(tstamp, predicted.max(0.0));
+        forecast.lower.insert(tstamp, (predicted - margin).max(0.0));
+        forecast.upper.insert(tstamp, (predicted + margin).max(0.0));
+
+        let carried = fit.alpha * (1.0 + damped * fit.beta)
+            + if season > 1 && h % season == 0 {
+                fit.gamma * (1.0 - fit.alpha)
+            } else {
+                0.0
+            };
+        spread += carried * carried;
+    }
+    Ok(forecast)
+}
+
+/// Forecast lines for `series` by label: the projection and the upper and
+/// lower bounds of its band. With a single series they are labeled
+/// `Forecast`, `Forecast upper` and `Forecast lower`, otherwise prefixed by
+/// the series.
+pub fn lines<K, V>(
+    series: &[(String, &BTreeMap<K, V>)],
+    model: Model,
+    horizon: Horizon,
+) -> Result<Vec<Line>>
+where
+    K: ToPrimitive + Copy + Debug,
+    V: ToPrimitive + Copy + Debug,
+{
+    let mut lines = vec![];
+    for (label, data) in series {
+        let forecast = forecast(*data, model, horizon)?;
+        if forecast.predicted.is_empty() {
+            continue;
+        }
+        let label = match series.len() {
+            1 => "Forecast".to_owned(),
+            _ => format!("{} forecast", label),
+        };
+        lines.push(Line::try_new(&label, &forecast.predicted)?);
+        lines.push(Line::try_new(format!("{} upper", label), &forecast.upper)?);
+        lines.push(Line::try_new(format!("{} lower", label), &forecast.lower)?);
+    }
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_days_keep_the_season_in_place() {
+        let weekly = [0.0, 10.0, 20.0, 30.0, 20.0, 10.0, 0.0];
+        let full: BTreeMap<u64, f64> = (0..28u64)
+            .map(|day| (day * MS_PER_DAY, 100.0 + weekly[day as usize % 7]))
+            .collect();
+        // a day whose value is on the line between its neighbours, so filling
+        // it in gives back the full series
+        let mut gapped = full.clone();
+        gapped.remove(&(8 * MS_PER_DAY));
+
+        let model = Model::HoltWinters { season: 7 };
+        let horizon = Horizon::Days(14);
+        assert_eq!(
+            forecast(&gapped, model, horizon).unwrap().predicted,
+            forecast(&full, model, horizon).unwrap().predicted
+        );
+    }
+}
@@ -0,0 +1,205 @@
+use crate::process::{resample::resolution, Line, MS_PER_DAY};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Months, Utc};
+use clap::ValueEnum;
+use num_traits::ToPrimitive;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+
+/// How missing samples are filled in. Either way they stay marked as
+/// interpolated in the graphs and never make it into the metrics.
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Interpolation {
+    /// On the straight line between the samples around the gap
+    Linear,
+    /// The last sample before the gap, repeated
+    ForwardFill,
+}
+
+/// Timestamps missing from `timestamps`, which must be sorted, going by
+/// their resolution: days for daily series, calendar months for monthly
+/// ones and the most common step for any other.
+pub fn missing(timestamps: &[u64]) -> Result<Vec<u64>> {
+    let Some(step) = resolution(timestamps) else {
+        return Ok(vec![]);
+    };
+    let monthly = (28 * MS_PER_DAY..=31 * MS_PER_DAY).contains(&step);
+
+    let mut missing = vec![];
+    for pair in timestamps.windows(2) {
+        let (previous, next) = (pair[0], pair[1]);
+        // samples may be a little off, e.g. months of different length
+        if next - previous <= step + step / 2 {
+            continue;
+        }
+        for i in 1.. {
+            let expected = if monthly {
+                let previous = i64::try_from(previous)
+                    .ok()
+                    .and_then(DateTime::<Utc>::from_timestamp_millis)
+                    .ok_or_else(|| anyhow!("timestamp {} is out of range", previous))?;
+                previous
+                    .checked_add_months(Months::new(i))
+                    .ok_or_else(|| anyhow!("{} months after {} is out of range", i, previous))?
+                    .timestamp_millis() as u64
+            } else {
+                previous + u64::from(i) * step
+            };
+            if expected + step / 2 >= next {
+                break;
+            }
+            missing.push(expected);
+        }
+    }
+    Ok(missing)
+}
+
+/// Value filled in by `interpolation` for the sample at `x` missing from
+/// `samples`, if the samples around it allow for one.
+pub fn interpolate(
+    samples: &BTreeMap<u64, f64>,
+    x: u64,
+    interpolation: Interpolation,
+) -> Option<f64> {
+    let before = samples.range(..x).next_back();
+    let after = samples.range(x..).next();
+    match (interpolation, before, after) {
+        (Interpolation::ForwardFill, Some((_, &y0)), _) => Some(y0),
+        (Interpolation::Linear, Some((&x0, &y0)), Some((&x1, &y1))) => {
+            Some(y0 + (y1 - y0) * (x - x0) as f64 / (x1 - x0) as f64)
+        }
+        _ => None,
+    }
+}
+
+/// Line of `series` labeled `label`, with a `null` value for every missing
+/// sample, or one filled in by `interpolation`. Also returns the timestamps
+/// of the missing samples.
+pub fn line<K, V>(
+    label: impl Into<String>,
+    series: &BTreeMap<K, V>,
+    interpolation: Option<Interpolation>,
+) -> Result<(Line, Vec<u64>)>
+where
+    K: ToPrimitive + Copy + Debug,
+    V: ToPrimitive + Copy + Debug,
+{
+    let samples = series
+        .iter()
+        .map(|(x, y)| {
+            let x = x.to_u64().ok_or(anyhow!("Failed casting {:?} to u64", x))?;
+            let y = y.to_f64().ok_or(anyhow!("Failed casting {:?} to f64", y))?;
+            Ok((x, y))
+        })
+        .collect::<Result<BTreeMap<_, _>>>()?;
+    let missing = missing(&samples.keys().copied().collect::<Vec<_>>())?;
+
+    let mut points: BTreeMap<u64, Option<f64>> =
+        samples.iter().map(|(&x, &y)| (x, Some(y))).collect();
+    let mut interpolated = vec![];
+    for &x in &missing {
+        let y = interpolation.and_then(|interpolation| interpolate(&samples, x, interpolation));
+        if y.is_some() {
+            interpolated.push(x as f64);
+        }
+        points.insert(x, y);
+    }
+
+    let (x, y) = points.into_iter().map(|(x, y)| (x as f64, y)).unzip();
+    Ok((
+        Line {
+            label: label.into(),
+            x,
+            y,
+            interpolated,
+        },
+        missing,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn day(day: u64) -> u64 {
+        day * MS_PER_DAY
+    }
+
+    #[test]
+    fn missing_days_are_found() {
+        let timestamps = [day(0), day(1), day(2), day(5), day(6), day(8)];
+        assert_eq!(missing(&timestamps).unwrap(), [day(3), day(4), day(7)]);
+        assert!(missing(&[day(0), day(1), day(2)]).unwrap().is_empty());
+        assert!(missing(&[day(0)]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn missing_months_follow_the_calendar() {
+        let month = |month| {
+            NaiveDate::from_ymd_opt(2023, month, 1)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+                .and_utc()
+                .timestamp_millis() as u64
+        };
+        // February is shorter than the other months, which is no gap
+        let timestamps = [month(1), month(2), month(3), month(4), month(7)];
+        assert_eq!(missing(&timestamps).unwrap(), [month(5), month(6)]);
+    }
+
+    #[test]
+    fn gaps_are_null_or_interpolated() {
+        let series = BTreeMap::from([
+            (day(0), 10.0),
+            (day(1), 20.0),
+            (day(4), 50.0),
+            (day(5), 60.0),
+        ]);
+        let y = |interpolation| {
+            let (line, missing) = line("pageviews", &series, interpolation).unwrap();
+            assert_eq!(missing, [day(2), day(3)]);
+            assert_eq!(line.x.len(), 6);
+            (line.y, line.interpolated)
+        };
+        assert_eq!(
+            y(None),
+            (
+                vec![Some(10.0), Some(20.0), None, None, Some(50.0), Some(60.0)],
+                vec![]
+            )
+        );
+        let filled = vec![day(2) as f64, day(3) as f64];
+        assert_eq!(
+            y(Some(Interpolation::Linear)),
+            (
+                vec![
+                    Some(10.0),
+                    Some(20.0),
+                    Some(30.0),
+                    Some(40.0),
+                    Some(50.0),
+                    Some(60.0)
+                ],
+                filled.clone()
+            )
+        );
+        assert_eq!(
+            y(Some(Interpolation::ForwardFill)),
+            (
+                vec![
+                    Some(10.0),
+                    Some(20.0),
+                    Some(20.0),
+                    Some(20.0),
+                    Some(50.0),
+                    Some(60.0)
+                ],
+                filled
+            )
+        );
+    }
+}
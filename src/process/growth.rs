@@ -0,0 +1,306 @@
+use crate::process::{
+    resample::resolution,
+    series::{Metadata, Metadatas},
+    Labels, Line, VictoriaMetric, VictoriaMetrics, MS_PER_DAY,
+};
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Months, Utc};
+use num_traits::ToPrimitive;
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+
+/// What a value is compared with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    WeekOverWeek,
+    MonthOverMonth,
+    YearOverYear,
+}
+
+impl Comparison {
+    const ALL: [Comparison; 3] = [
+        Comparison::WeekOverWeek,
+        Comparison::MonthOverMonth,
+        Comparison::YearOverYear,
+    ];
+
+    /// Label of the lines of the comparison.
+    fn label(&self) -> &'static str {
+        match self {
+            Comparison::WeekOverWeek => "week over week",
+            Comparison::MonthOverMonth => "month over month",
+            Comparison::YearOverYear => "year over year",
+        }
+    }
+
+    /// Value of the `period` label of the metrics of the comparison.
+    fn period(&self) -> &'static str {
+        match self {
+            Comparison::WeekOverWeek => "week",
+            Comparison::MonthOverMonth => "month",
+            Comparison::YearOverYear => "year",
+        }
+    }
+
+    /// For daily series, the number of days summed up and how many days
+    /// earlier the sum compared with starts. Whole weeks, so the same
+    /// weekdays are compared.
+    fn days(&self) -> (u64, u64) {
+        match self {
+            Comparison::WeekOverWeek => (7, 7),
+            Comparison::MonthOverMonth => (28, 28),
+            Comparison::YearOverYear => (28, 52 * 7),
+        }
+    }
+
+    /// For monthly series, how many months earlier the month compared with
+    /// is, if the comparison makes sense at all.
+    fn months(&self) -> Option<u32> {
+        match self {
+            Comparison::WeekOverWeek => None,
+            Comparison::MonthOverMonth => Some(1),
+            Comparison::YearOverYear => Some(12),
+        }
+    }
+}
+
+fn percent(current: f64, previous: f64) -> Option<f64> {
+    (previous != 0.0).then(|| (current - previous) / previous * 100.0)
+}
+
+/// Changes in percent of `series` (timestamps in milliseconds) for every
+/// comparison that fits its resolution, keyed by the timestamp of the later
+/// value.
+///
+/// Daily series compare sums over whole weeks, so single days and weekends
+/// do not dominate: the last 7 days with the 7 before, the last 28 days with
+/// the 28 before and with the same 28 days 52 weeks earlier. Monthly series
+/// compare a month with the one before and the same month a year earlier.
+/// Other resolutions have no comparisons, nor do values compared with
+/// missing or zero ones.
+pub fn growth<K, V>(series: &BTreeMap<K, V>) -> Result<Vec<(Comparison, BTreeMap<u64, f64>)>>
+where
+    K: ToPrimitive + Copy + Debug,
+    V: ToPrimitive + Copy + Debug,
+{
+    let samples = series
+        .iter()
+        .map(|(x, y)| {
+            let x = x.to_u64().ok_or(anyhow!("Failed casting {:?} to u64", x))?;
+            let y = y.to_f64().ok_or(anyhow!("Failed casting {:?} to f64", y))?;
+            Ok((x, y))
+        })
+        .collect::<Result<BTreeMap<_, _>>>()?;
+    let timestamps: Vec<_> = samples.keys().copied().collect();
+
+    let mut comparisons = vec![];
+    match resolution(&timestamps) {
+        Some(MS_PER_DAY) => {
+            // sum of the `days` days up to and including `end`, if all exist
+            let sum = |end: u64, days: u64| -> Option<f64> {
+                (0..days)
+                    .map(|day| samples.get(&end.checked_sub(day * MS_PER_DAY)?).copied())
+                    .sum()
+            };
+            for comparison in Comparison::ALL {
+                let (days, lag) = comparison.days();
+                let changes = timestamps
+                    .iter()
+                    .filter_map(|&tstamp| {
+                        let current = sum(tstamp, days)?;
+                        let previous = sum(tstamp.checked_sub(lag * MS_PER_DAY)?, days)?;
+                        Some((tstamp, percent(current, previous)?))
+                    })
+                    .collect();
+                comparisons.push((comparison, changes));
+            }
+        }
+        // between 28 and 31 days
+        Some(step) if (28 * MS_PER_DAY..=31 * MS_PER_DAY).contains(&step) => {
+            // the sample closest to `lag` months before `tstamp`, if it is
+            // within half a month of it, as samples need not fall on the
+            // first of the month
+            let earlier = |tstamp: u64, lag: u32| -> Option<f64> {
+                let target = DateTime::<Utc>::from_timestamp_millis(i64::try_from(tstamp).ok()?)?
+                    .checked_sub_months(Months::new(lag))?
+                    .timestamp_millis();
+                let target = u64::try_from(target).ok()?;
+                let before = samples.range(..=target).next_back();
+                let after = samples.range(target..).next();
+                let (&closest, &value) = before
+                    .into_iter()
+                    .chain(after)
+                    .min_by_key(|(&x, _)| x.abs_diff(target))?;
+                (closest.abs_diff(target) <= step / 2).then_some(value)
+            };
+            for comparison in Comparison::ALL {
+                let Some(lag) = comparison.months() else {
+                    continue;
+                };
+                let changes = samples
+                    .iter()
+                    .filter_map(|(&tstamp, &current)| {
+                        Some((tstamp, percent(current, earlier(tstamp, lag)?)?))
+                    })
+                    .collect();
+                comparisons.push((comparison, changes));
+            }
+        }
+        _ => {}
+    }
+    Ok(comparisons)
+}
+
+/// Growth lines for `series` by label, one per comparison with any values.
+/// With a single series they are labeled by the comparison, e.g.
+/// `Year over year`, otherwise by series and comparison.
+pub fn lines<K, V>(series: &[(String, &BTreeMap<K, V>)]) -> Result<Vec<Line>>
+where
+    K: ToPrimitive + Copy + Debug,
+    V: ToPrimitive + Copy + Debug,
+{
+    let mut lines = vec![];
+    for (label, data) in series {
+        for (comparison, changes) in growth(*data)? {
+            if changes.is_empty() {
+                continue;
+            }
+            let label = match series.len() {
+                1 => {
+                    let mut label = comparison.label().to_owned();
+                    label[..1].make_ascii_uppercase();
+                    label
+                }
+                _ => format!("{} {}", label, comparison.label()),
+            };
+            lines.push(Line::try_new(label, &changes)?);
+        }
+    }
+    Ok(lines)
+}
+
+/// Metadata of the `NAME_growth` metrics of the metrics in `metadata`.
+pub fn metadata(metadata: &Metadatas) -> Metadatas {
+    metadata
+        .iter()
+        .map(|(name, metadata)| {
+            (
+                format!("{}_growth", name),
+                Metadata {
+                    unit: "percent".to_owned(),
+                    description: format!(
+                        "{}, change from the week, month or year before as by the period label",
+                        metadata.description
+                    ),
+                    resolution: metadata.resolution,
+                },
+            )
+        })
+        .collect()
+}
+
+/// A `NAME_growth` series for every comparison of each of `victoriametrics`,
+/// with the labels of the metric and the compared `period`, in percent.
+pub fn victoriametrics(victoriametrics: &[VictoriaMetric]) -> Result<VictoriaMetrics> {
+    let mut growths = vec![];
+    for victoriametric in victoriametrics {
+        let labels = victoriametric.labels();
+        if labels.contains_key("period") {
+            bail!(
+                "metric {} has a period label, which growth uses itself",
+                victoriametric.metric
+            );
+        }
+
+        let series: BTreeMap<u64, f64> = victoriametric
+            .timestamps
+            .iter()
+            .copied()
+            .zip(victoriametric.values.iter().copied())
+            .collect();
+        for (comparison, changes) in growth(&series)? {
+            if changes.is_empty() {
+                continue;
+            }
+            let mut metric = Labels::new(format!("{}_growth", labels.name()))?
+                .with("period", comparison.period())?;
+            for (name, value) in labels.labels() {
+                metric = metric.with(name, value)?;
+            }
+            let (timestamps, values) = changes.into_iter().unzip();
+            growths.push(VictoriaMetric {
+                metric,
+                values,
+                timestamps,
+            });
+        }
+    }
+    Ok(growths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn changes(series: &BTreeMap<u64, f64>, comparison: Comparison) -> Option<BTreeMap<u64, f64>> {
+        growth(series)
+            .unwrap()
+            .into_iter()
+            .find(|(compared, _)| *compared == comparison)
+            .map(|(_, changes)| changes)
+    }
+
+    #[test]
+    fn weeks_with_a_missing_day_are_not_compared() {
+        // 30 days without the 6th, doubling from the 21st on
+        let series: BTreeMap<u64, f64> = (0..30u64)
+            .filter(|&day| day != 5)
+            .map(|day| (day * MS_PER_DAY, if day < 20 { 1.0 } else { 2.0 }))
+            .collect();
+        let week = changes(&series, Comparison::WeekOverWeek).unwrap();
+        // from the 20th day on, the two weeks up to a day no longer miss one
+        assert_eq!(
+            week.keys().copied().collect::<Vec<_>>(),
+            (19..30).map(|day| day * MS_PER_DAY).collect::<Vec<_>>()
+        );
+        assert_eq!(week[&(19 * MS_PER_DAY)], 0.0);
+        assert_eq!(week[&(26 * MS_PER_DAY)], 100.0);
+        assert!(changes(&series, Comparison::MonthOverMonth)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn months_are_compared_with_the_calendar_month_before() {
+        let month = |year, month| {
+            NaiveDate::from_ymd_opt(year, month, 1)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+                .and_utc()
+                .timestamp_millis() as u64
+        };
+        // no March, which April and the March a year later cannot be
+        // compared with
+        let mut series: BTreeMap<u64, f64> = (1..=12)
+            .filter(|&m| m != 3)
+            .map(|m| (month(2022, m), if m == 4 { 150.0 } else { 100.0 }))
+            .collect();
+        series.insert(month(2023, 1), 120.0);
+        series.insert(month(2023, 3), 100.0);
+
+        let monthly = changes(&series, Comparison::MonthOverMonth).unwrap();
+        assert_eq!(monthly[&month(2022, 2)], 0.0);
+        assert!(!monthly.contains_key(&month(2022, 4)));
+        assert!((monthly[&month(2022, 5)] - -100.0 / 3.0).abs() < 1e-9);
+        assert!(!monthly.contains_key(&month(2023, 3)));
+
+        let yearly = changes(&series, Comparison::YearOverYear).unwrap();
+        assert_eq!(
+            yearly.into_iter().collect::<Vec<_>>(),
+            [(month(2023, 1), 20.0)]
+        );
+        assert!(changes(&series, Comparison::WeekOverWeek).is_none());
+    }
+}
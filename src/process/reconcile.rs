@@ -0,0 +1,365 @@
+use crate::process::{to_date, MS_PER_DAY};
+use anyhow::{bail, Result};
+use chrono::{NaiveDate, NaiveTime};
+use clap::ValueEnum;
+use log::warn;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// What to do when snapshots disagree about a sample, as when Netlify
+/// revises a recent day.
+#[derive(ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Reconciliation {
+    /// Stop processing
+    #[default]
+    Fail,
+    /// Take the value of the snapshot taken last
+    Newest,
+    /// Keep the value of the snapshot taken first
+    Oldest,
+    /// Take the largest value, counts only ever being revised upwards
+    Max,
+}
+
+impl fmt::Display for Reconciliation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Reconciliation::Fail => write!(f, "fail"),
+            Reconciliation::Newest => write!(f, "newest"),
+            Reconciliation::Oldest => write!(f, "oldest"),
+            Reconciliation::Max => write!(f, "max"),
+        }
+    }
+}
+
+/// Writes a timestamp in milliseconds as its day, e.g. `2023-02-01`, for the
+/// report to be read by people. Timestamps within a day are kept as they
+/// are, so nothing is lost.
+fn day<S: Serializer>(ms: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+    if ms.is_multiple_of(MS_PER_DAY) {
+        serializer.serialize_str(&to_date(*ms))
+    } else {
+        serializer.serialize_u64(*ms)
+    }
+}
+
+/// Reads what [`day`] wrote.
+fn from_day<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Day {
+        Day(String),
+        Ms(u64),
+    }
+    match Day::deserialize(deserializer)? {
+        Day::Ms(ms) => Ok(ms),
+        Day::Day(day) => NaiveDate::parse_from_str(&day, "%Y-%m-%d")
+            .ok()
+            .and_then(|date| {
+                u64::try_from(date.and_time(NaiveTime::MIN).and_utc().timestamp_millis()).ok()
+            })
+            .ok_or_else(|| de::Error::custom(format!("invalid day {:?}", day))),
+    }
+}
+
+/// A value of a sample as a snapshot file has it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Report {
+    pub file: PathBuf,
+    /// When the snapshot was taken, in milliseconds
+    #[serde(
+        rename = "snapshot",
+        serialize_with = "day",
+        deserialize_with = "from_day"
+    )]
+    pub taken: u64,
+    pub value: f64,
+}
+
+impl Report {
+    /// Orders reports by when their snapshot was taken, then by file, so
+    /// snapshots of the same day are in the order they were collected in.
+    fn age(&self) -> (u64, &PathBuf) {
+        (self.taken, &self.file)
+    }
+}
+
+/// Snapshots disagreeing about a sample, and the value taken.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Conflict {
+    pub metric: String,
+    #[serde(rename = "day", serialize_with = "day")]
+    pub timestamp: u64,
+    /// Every report of the sample, in the order they were added
+    pub reports: Vec<Report>,
+    pub chosen: f64,
+    pub reconciliation: Reconciliation,
+}
+
+/// Whether the last of `reports` is the first to have its value.
+fn is_new_value(reports: &[Report]) -> bool {
+    let Some((report, earlier_reports)) = reports.split_last() else {
+        return false;
+    };
+    !earlier_reports
+        .iter()
+        .any(|earlier| earlier.value == report.value)
+}
+
+/// Value taken by `reconciliation` from `reports` that disagree, none when
+/// it is to fail.
+fn choose(reconciliation: Reconciliation, reports: &[Report]) -> Option<f64> {
+    match reconciliation {
+        Reconciliation::Fail => None,
+        Reconciliation::Newest => reports.iter().max_by(|a, b| a.age().cmp(&b.age())),
+        Reconciliation::Oldest => reports.iter().min_by(|a, b| a.age().cmp(&b.age())),
+        Reconciliation::Max => reports.iter().max_by(|a, b| a.value.total_cmp(&b.value)),
+    }
+    .map(|report| report.value)
+}
+
+/// Keeps track of the values snapshots report for each sample, deciding by a
+/// [`Reconciliation`] where they disagree.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct Reconciler {
+    reconciliation: Reconciliation,
+    /// Metric -> timestamp -> reports
+    reports: BTreeMap<String, BTreeMap<u64, Vec<Report>>>,
+}
+
+impl Reconciler {
+    pub fn new(reconciliation: Reconciliation) -> Reconciler {
+        Reconciler {
+            reconciliation,
+            ..Default::default()
+        }
+    }
+
+    /// Value of the sample of `metric` at `timestamp` with `report` added to
+    /// what earlier snapshots said about it. A disagreement is an error when
+    /// reconciliation is to fail.
+    pub fn report(&mut self, metric: &str, timestamp: u64, report: Report) -> Result<f64> {
+        let reports = self
+            .reports
+            .entry(metric.to_owned())
+            .or_default()
+            .entry(timestamp)
+            .or_default();
+        reports.push(report);
+        let report = reports.last().expect("just pushed");
+        let Some(earlier) = reports.iter().find(|earlier| earlier.value != report.value) else {
+            return Ok(report.value);
+        };
+
+        let Some(chosen) = choose(self.reconciliation, reports) else {
+            bail!(
+                "data mistmatch on {} ({}): {} from file {} and {} found in file {}",
+                to_date(timestamp),
+                metric,
+                earlier.value,
+                earlier.file.display(),
+                report.value,
+                report.file.display()
+            );
+        };
+        // once per value, rather than for every snapshot repeating it
+        if is_new_value(reports) {
+            warn!(
+                "data mistmatch on {} ({}): {} found in file {}, taking {} ({})",
+                to_date(timestamp),
+                metric,
+                report.value,
+                report.file.display(),
+                chosen,
+                self.reconciliation
+            );
+        }
+        Ok(chosen)
+    }
+
+    /// Takes back every report from the file `path`, or from the files in
+    /// the archive `path`. Returns the samples they were about, whose
+    /// [`value`](Reconciler::value) may have changed.
+    pub fn remove(&mut self, path: &Path) -> Vec<(String, u64)> {
+        let mut samples = vec![];
+        for (metric, reports_by_timestamp) in &mut self.reports {
+            reports_by_timestamp.retain(|&timestamp, reports| {
+                let count = reports.len();
+                reports.retain(|report| !report.file.starts_with(path));
+                if reports.len() != count {
+                    samples.push((metric.clone(), timestamp));
+                }
+                !reports.is_empty()
+            });
+        }
+        samples
+    }
+
+    /// Value of the sample of `metric` at `timestamp` by the reports it has,
+    /// none if there are none.
+    pub fn value(&self, metric: &str, timestamp: u64) -> Option<f64> {
+        let reports = self.reports.get(metric)?.get(&timestamp)?;
+        let first = reports.first()?;
+        if reports.iter().all(|report| report.value == first.value) {
+            Some(first.value)
+        } else {
+            choose(self.reconciliation, reports)
+        }
+    }
+
+    /// Every sample snapshots disagreed about, by metric and timestamp.
+    pub fn conflicts(&self) -> Vec<Conflict> {
+        let mut conflicts = vec![];
+        for (metric, samples) in &self.reports {
+            for (&timestamp, reports) in samples {
+                if reports
+                    .iter()
+                    .all(|report| report.value == reports[0].value)
+                {
+                    continue;
+                }
+                let Some(chosen) = choose(self.reconciliation, reports) else {
+                    continue;
+                };
+                conflicts.push(Conflict {
+                    metric: metric.clone(),
+                    timestamp,
+                    reports: reports.clone(),
+                    chosen,
+                    reconciliation: self.reconciliation,
+                });
+            }
+        }
+        conflicts
+    }
+
+    pub fn reconciliation(&self) -> Reconciliation {
+        self.reconciliation
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(file: &str, taken: u64, value: f64) -> Report {
+        Report {
+            file: PathBuf::from(file),
+            taken,
+            value,
+        }
+    }
+
+    /// Value chosen by `reconciliation` for reports added in order.
+    fn reconcile(reconciliation: Reconciliation, reports: &[Report]) -> Result<f64> {
+        let mut reconciler = Reconciler::new(reconciliation);
+        let mut value = f64::NAN;
+        for report in reports {
+            value = reconciler.report("netlify.pageviews", 0, report.clone())?;
+        }
+        Ok(value)
+    }
+
+    #[test]
+    fn agreeing_reports_are_no_conflict() {
+        let mut reconciler = Reconciler::new(Reconciliation::Fail);
+        for file in ["a.json", "b.json"] {
+            let value = reconciler
+                .report("netlify.pageviews", 0, report(file, 1, 10.0))
+                .unwrap();
+            assert_eq!(value, 10.0);
+        }
+        assert!(reconciler.conflicts().is_empty());
+    }
+
+    #[test]
+    fn fail_rejects_disagreeing_reports() {
+        let reports = [report("a.json", 1, 10.0), report("b.json", 2, 12.0)];
+        let error = reconcile(Reconciliation::Fail, &reports).unwrap_err();
+        assert!(error.to_string().contains("data mistmatch"), "{}", error);
+    }
+
+    #[test]
+    fn policies_choose_by_age_or_value() {
+        // added out of order, so the choice cannot just be the first or last
+        let reports = [
+            report("b.json", 2, 12.0),
+            report("c.json", 3, 11.0),
+            report("a.json", 1, 10.0),
+        ];
+        assert_eq!(reconcile(Reconciliation::Newest, &reports).unwrap(), 11.0);
+        assert_eq!(reconcile(Reconciliation::Oldest, &reports).unwrap(), 10.0);
+        assert_eq!(reconcile(Reconciliation::Max, &reports).unwrap(), 12.0);
+    }
+
+    #[test]
+    fn snapshots_of_the_same_day_are_ordered_by_file() {
+        let reports = [report("b.json", 1, 12.0), report("a.json", 1, 10.0)];
+        assert_eq!(reconcile(Reconciliation::Newest, &reports).unwrap(), 12.0);
+        assert_eq!(reconcile(Reconciliation::Oldest, &reports).unwrap(), 10.0);
+    }
+
+    #[test]
+    fn conflicts_keep_every_report() {
+        let mut reconciler = Reconciler::new(Reconciliation::Max);
+        for report in [
+            report("a.json", 1, 10.0),
+            report("b.json", 2, 12.0),
+            report("c.json", 3, 12.0),
+        ] {
+            reconciler.report("netlify.visitors", 5, report).unwrap();
+        }
+        let conflicts = reconciler.conflicts();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].metric, "netlify.visitors");
+        assert_eq!(conflicts[0].timestamp, 5);
+        assert_eq!(conflicts[0].reports.len(), 3);
+        assert_eq!(conflicts[0].chosen, 12.0);
+    }
+
+    #[test]
+    fn removed_reports_no_longer_count() {
+        let mut reconciler = Reconciler::new(Reconciliation::Newest);
+        for (timestamp, report) in [
+            (5, report("a.json", 1, 10.0)),
+            (5, report("2023.tar.gz/b.json", 2, 12.0)),
+            (6, report("2023.tar.gz/b.json", 2, 7.0)),
+        ] {
+            reconciler
+                .report("netlify.pageviews", timestamp, report)
+                .unwrap();
+        }
+        assert_eq!(reconciler.value("netlify.pageviews", 5), Some(12.0));
+
+        let removed = reconciler.remove(Path::new("2023.tar.gz"));
+        assert_eq!(
+            removed,
+            [
+                ("netlify.pageviews".to_owned(), 5),
+                ("netlify.pageviews".to_owned(), 6)
+            ]
+        );
+        assert_eq!(reconciler.value("netlify.pageviews", 5), Some(10.0));
+        assert_eq!(reconciler.value("netlify.pageviews", 6), None);
+        assert!(reconciler.conflicts().is_empty());
+    }
+
+    #[test]
+    fn only_new_values_are_warned_about() {
+        let reports = [
+            report("a.json", 1, 10.0),
+            report("b.json", 2, 12.0),
+            report("c.json", 3, 12.0),
+            report("d.json", 4, 10.0),
+            report("e.json", 5, 13.0),
+        ];
+        let new: Vec<_> = (1..=reports.len())
+            .map(|n| is_new_value(&reports[..n]))
+            .collect();
+        assert_eq!(new, [true, true, false, false, true]);
+    }
+}
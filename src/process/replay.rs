@@ -0,0 +1,131 @@
+use crate::{
+    config::Config,
+    gtrends, netlify,
+    output::Outputs,
+    process::{
+        combine, derive, is_derived, merge,
+        series::{self, Series},
+        Labels, Options, Processed, VictoriaMetric, VictoriaMetrics,
+    },
+};
+use anyhow::Result;
+use clap::Parser;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+#[derive(Parser, Debug, Clone, Default)]
+#[command(version, about, author, long_about = None)]
+pub struct Cli {
+    /// VictoriaMetrics JSON line files of earlier runs, or directories of them
+    #[clap(long = "victoriametrics", required = true, value_parser = clap::value_parser!(PathBuf))]
+    exports: Vec<PathBuf>,
+
+    /// Directory where fresh Netlify data has been collected, merged with what is read back
+    #[clap(long, value_parser = clap::value_parser!(PathBuf))]
+    netlify_dir: Option<PathBuf>,
+
+    /// File where fresh Google Trends data has been collected, merged with what is read back
+    #[clap(long, value_parser = clap::value_parser!(PathBuf))]
+    gtrends_data: Option<PathBuf>,
+
+    #[clap(skip)]
+    netlify_state: Option<PathBuf>,
+
+    #[clap(skip)]
+    netlify_options: Options,
+
+    #[clap(skip)]
+    gtrends_options: Options,
+
+    #[command(flatten)]
+    outputs: Outputs,
+}
+
+impl Cli {
+    /// Fills the outputs and processing options not given on the command line
+    /// from `config`. Fresh data is only merged when passed explicitly.
+    pub fn with_config(&self, config: &Config) -> Cli {
+        Cli {
+            netlify_state: config.sources.netlify.state_of(self.netlify_dir.as_deref()),
+            netlify_options: config.sources.netlify.options(),
+            gtrends_options: config.sources.gtrends.options(),
+            outputs: self.outputs.with_defaults(&config.outputs),
+            ..self.clone()
+        }
+    }
+}
+
+/// Renders the series read back into the outputs, merged with the fresh data
+/// of the sources given, if any. Where both have a sample of the same series
+/// and timestamp, the fresh one is taken, as sources revise recent days.
+/// Anomalies and growth are derived again over the merged series, as they
+/// depend on all of it.
+///
+/// Graphs and gaps only cover the fresh data, the exports have neither.
+pub async fn run(args: &Cli) -> Result<()> {
+    let mut metadata = netlify::process::metadata();
+    metadata.extend(gtrends::process::metadata());
+
+    let mut fresh = vec![];
+    if let Some(dir) = &args.netlify_dir {
+        fresh.push(netlify::process::process_dir_cached(
+            dir,
+            args.netlify_state.as_deref(),
+            false,
+            &args.netlify_options,
+        )?);
+    }
+    if let Some(data) = &args.gtrends_data {
+        fresh.push(gtrends::process::process_file(data, &args.gtrends_options)?);
+    }
+    let fresh = combine(fresh)?;
+
+    let exports = series::read(&args.exports, &metadata)?
+        .iter()
+        .map(Series::victoriametric)
+        .collect::<Vec<_>>();
+    let mut victoriametrics = replaced(exports, &fresh.victoriametrics);
+    victoriametrics.extend(fresh.victoriametrics);
+    victoriametrics.retain(|victoriametric| !is_derived(victoriametric.labels()));
+    metadata.extend(fresh.metadata);
+
+    let processed = Processed {
+        graphs: fresh.graphs,
+        victoriametrics: derive(merge(victoriametrics)?)?,
+        gaps: fresh.gaps,
+        metadata,
+        conflicts: fresh.conflicts,
+    };
+    args.outputs.write(&processed).await
+}
+
+/// `victoriametrics` without the samples `fresh` has too.
+fn replaced(victoriametrics: VictoriaMetrics, fresh: &VictoriaMetrics) -> VictoriaMetrics {
+    let mut fresh_samples = BTreeMap::<&Labels, BTreeSet<u64>>::new();
+    for victoriametric in fresh {
+        fresh_samples
+            .entry(victoriametric.labels())
+            .or_default()
+            .extend(&victoriametric.timestamps);
+    }
+
+    victoriametrics
+        .into_iter()
+        .map(|victoriametric| {
+            let Some(tstamps) = fresh_samples.get(victoriametric.labels()) else {
+                return victoriametric;
+            };
+            let (timestamps, values) = victoriametric
+                .timestamps
+                .iter()
+                .zip(&victoriametric.values)
+                .filter(|(tstamp, _)| !tstamps.contains(tstamp))
+                .unzip();
+            VictoriaMetric {
+                timestamps,
+                values,
+                ..victoriametric
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,274 @@
+use crate::process::{day_of, Line};
+use anyhow::{anyhow, Result};
+use chrono::{Datelike, Days, Months, NaiveDate};
+use num_traits::ToPrimitive;
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+
+/// Calendar period samples are grouped into, in UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Period {
+    /// ISO week, starting on Monday
+    Week,
+    Month,
+    Quarter,
+    Year,
+}
+
+/// How the samples of a period are combined into one value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregation {
+    Sum,
+    Mean,
+    Max,
+    /// The latest sample of the period
+    Last,
+}
+
+/// A resampled value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bucket {
+    pub value: f64,
+    /// Number of samples the value was aggregated from
+    pub samples: usize,
+    /// Whether the period has as many samples as the resolution of the series
+    /// lets expect, e.g. one for every day of a month of daily data. Sums of
+    /// incomplete periods, like the current month, are not comparable to the
+    /// others.
+    pub complete: bool,
+}
+
+impl Period {
+    /// First day of the period `date` is in.
+    fn start(&self, date: NaiveDate) -> NaiveDate {
+        match self {
+            Period::Week => date - Days::new(date.weekday().num_days_from_monday().into()),
+            Period::Month => date.with_day(1).expect("every month has a first day"),
+            Period::Quarter => NaiveDate::from_ymd_opt(date.year(), (date.month0() / 3) * 3 + 1, 1)
+                .expect("every quarter has a first day"),
+            Period::Year => {
+                NaiveDate::from_ymd_opt(date.year(), 1, 1).expect("every year has a first day")
+            }
+        }
+    }
+
+    /// First day of the period after the one starting on `start`.
+    fn next(&self, start: NaiveDate) -> NaiveDate {
+        match self {
+            Period::Week => start + Days::new(7),
+            Period::Month => start + Months::new(1),
+            Period::Quarter => start + Months::new(3),
+            Period::Year => start + Months::new(12),
+        }
+    }
+}
+
+fn to_ms(date: NaiveDate) -> u64 {
+    date.and_hms_opt(0, 0, 0)
+        .expect("midnight exists")
+        .and_utc()
+        .timestamp_millis() as u64
+}
+
+/// Typical distance between two samples, the median so a few gaps do not
+/// change it.
+pub(crate) fn resolution(timestamps: &[u64]) -> Option<u64> {
+    let mut steps: Vec<_> = timestamps
+        .windows(2)
+        .map(|pair| pair[1] - pair[0])
+        .collect();
+    steps.sort_unstable();
+    steps.get(steps.len() / 2).copied()
+}
+
+/// Groups `series` (timestamps in milliseconds) into calendar periods,
+/// keyed by the start of the period at midnight UTC.
+pub fn resample<K, V>(
+    series: &BTreeMap<K, V>,
+    period: Period,
+    aggregation: Aggregation,
+) -> Result<BTreeMap<u64, Bucket>>
+where
+    K: ToPrimitive + Copy + Debug,
+    V: ToPrimitive + Copy + Debug,
+{
+    let mut periods = BTreeMap::<NaiveDate, Vec<(u64, f64)>>::new();
+    let mut timestamps = vec![];
+    for (tstamp, value) in series {
+        let tstamp = tstamp
+            .to_u64()
+            .ok_or(anyhow!("Failed casting {:?} to u64", tstamp))?;
+        let value = value
+            .to_f64()
+            .ok_or(anyhow!("Failed casting {:?} to f64", value))?;
+        periods
+            .entry(period.start(
+                day_of(tstamp).ok_or_else(|| anyhow!("timestamp {} is out of range", tstamp))?,
+            ))
+            .or_default()
+            .push((tstamp, value));
+        timestamps.push(tstamp);
+    }
+    let resolution = resolution(&timestamps);
+
+    Ok(periods
+        .into_iter()
+        .map(|(start, samples)| {
+            let length = to_ms(period.next(start)) - to_ms(start);
+            let expected = match resolution {
+                Some(resolution) => ((length as f64 / resolution as f64).round() as usize).max(1),
+                // a single sample says nothing about what to expect
+                None => usize::MAX,
+            };
+            let values = samples.iter().map(|(_, value)| *value);
+            let value = match aggregation {
+                Aggregation::Sum => values.sum(),
+                Aggregation::Mean => values.sum::<f64>() / samples.len() as f64,
+                Aggregation::Max => values.fold(f64::NEG_INFINITY, f64::max),
+                Aggregation::Last => samples.last().expect("periods are never empty").1,
+            };
+            (
+                to_ms(start),
+                Bucket {
+                    value,
+                    samples: samples.len(),
+                    complete: samples.len() >= expected,
+                },
+            )
+        })
+        .collect())
+}
+
+/// Resamples `series` into a line of the complete periods, labeled `label`,
+/// and, if there are any, a line of the incomplete ones, labeled
+/// `label (partial)`, so they can be told apart in a graph.
+pub fn lines<K, V>(
+    label: &str,
+    series: &BTreeMap<K, V>,
+    period: Period,
+    aggregation: Aggregation,
+) -> Result<Vec<Line>>
+where
+    K: ToPrimitive + Copy + Debug,
+    V: ToPrimitive + Copy + Debug,
+{
+    let (complete, partial): (BTreeMap<_, _>, BTreeMap<_, _>) =
+        resample(series, period, aggregation)?
+            .into_iter()
+            .partition(|(_, bucket)| bucket.complete);
+    let mut lines = vec![Line::try_new(
+        label,
+        &complete
+            .into_iter()
+            .map(|(x, bucket)| (x, bucket.value))
+            .collect(),
+    )?];
+    if !partial.is_empty() {
+        lines.push(Line::try_new(
+            format!("{} (partial)", label),
+            &partial
+                .into_iter()
+                .map(|(x, bucket)| (x, bucket.value))
+                .collect(),
+        )?);
+    }
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::MS_PER_DAY;
+
+    fn ms(year: i32, month: u32, day: u32) -> u64 {
+        to_ms(NaiveDate::from_ymd_opt(year, month, day).unwrap())
+    }
+
+    /// A sample of 1 every day from `first` to `last`, both included.
+    fn daily(first: u64, last: u64) -> BTreeMap<u64, f64> {
+        (first..=last)
+            .step_by(MS_PER_DAY as usize)
+            .map(|tstamp| (tstamp, 1.0))
+            .collect()
+    }
+
+    #[test]
+    fn weeks_start_on_monday() {
+        // Sunday 1 January 2023 is still in the week of Monday 26 December
+        let series = daily(ms(2022, 12, 26), ms(2023, 1, 8));
+        let buckets = resample(&series, Period::Week, Aggregation::Sum).unwrap();
+        assert_eq!(
+            buckets.keys().copied().collect::<Vec<_>>(),
+            [ms(2022, 12, 26), ms(2023, 1, 2)]
+        );
+        assert!(buckets
+            .values()
+            .all(|bucket| bucket.value == 7.0 && bucket.complete));
+    }
+
+    #[test]
+    fn periods_split_at_their_first_day() {
+        let series = BTreeMap::from([
+            (ms(2022, 12, 31), 1.0),
+            (ms(2023, 1, 1), 2.0),
+            (ms(2023, 3, 31), 3.0),
+            (ms(2023, 4, 1), 4.0),
+        ]);
+        let starts = |period| {
+            resample(&series, period, Aggregation::Sum)
+                .unwrap()
+                .into_iter()
+                .map(|(start, bucket)| (start, bucket.value))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            starts(Period::Month),
+            [
+                (ms(2022, 12, 1), 1.0),
+                (ms(2023, 1, 1), 2.0),
+                (ms(2023, 3, 1), 3.0),
+                (ms(2023, 4, 1), 4.0)
+            ]
+        );
+        assert_eq!(
+            starts(Period::Quarter),
+            [
+                (ms(2022, 10, 1), 1.0),
+                (ms(2023, 1, 1), 5.0),
+                (ms(2023, 4, 1), 4.0)
+            ]
+        );
+        assert_eq!(
+            starts(Period::Year),
+            [(ms(2022, 1, 1), 1.0), (ms(2023, 1, 1), 9.0)]
+        );
+    }
+
+    #[test]
+    fn partial_periods_are_incomplete() {
+        // all of February, and the last two days of January
+        let series = daily(ms(2023, 1, 30), ms(2023, 2, 28));
+        let buckets = resample(&series, Period::Month, Aggregation::Sum).unwrap();
+        let january = buckets[&ms(2023, 1, 1)];
+        let february = buckets[&ms(2023, 2, 1)];
+        assert_eq!((january.samples, january.complete), (2, false));
+        assert_eq!((february.samples, february.complete), (28, true));
+        assert_eq!(february.value, 28.0);
+    }
+
+    #[test]
+    fn aggregations_combine_a_period() {
+        let series = BTreeMap::from([
+            (ms(2023, 2, 1), 4.0),
+            (ms(2023, 2, 2), 8.0),
+            (ms(2023, 2, 3), 3.0),
+        ]);
+        let value = |aggregation| {
+            resample(&series, Period::Month, aggregation).unwrap()[&ms(2023, 2, 1)].value
+        };
+        assert_eq!(value(Aggregation::Sum), 15.0);
+        assert_eq!(value(Aggregation::Mean), 5.0);
+        assert_eq!(value(Aggregation::Max), 8.0);
+        assert_eq!(value(Aggregation::Last), 3.0);
+    }
+}
@@ -0,0 +1,167 @@
+use crate::process::{
+    gaps::{self, Interpolation},
+    merge, read_victoriametrics_paths,
+    resample::resolution,
+    Labels, Line, VictoriaMetric, MS_PER_DAY,
+};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use num_traits::ToPrimitive;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::path::PathBuf;
+
+/// How often a series has a sample.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Resolution {
+    Daily,
+    Monthly,
+}
+
+impl Resolution {
+    /// Resolution of samples at `timestamps` in milliseconds, if they are
+    /// daily or monthly.
+    pub fn of(timestamps: &[u64]) -> Option<Resolution> {
+        match resolution(timestamps)? {
+            MS_PER_DAY => Some(Resolution::Daily),
+            step if (28 * MS_PER_DAY..=31 * MS_PER_DAY).contains(&step) => {
+                Some(Resolution::Monthly)
+            }
+            _ => None,
+        }
+    }
+
+    /// Unit of time of a sample, e.g. for labeling smoothed lines.
+    pub fn unit(&self) -> &'static str {
+        match self {
+            Resolution::Daily => "day",
+            Resolution::Monthly => "month",
+        }
+    }
+}
+
+/// What the samples of a metric mean, shared by all its series.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    /// What is counted, e.g. `pageviews`
+    pub unit: String,
+    pub description: String,
+    /// `None` for metrics mixing series of different resolutions
+    pub resolution: Option<Resolution>,
+}
+
+/// Metadata by metric name.
+pub type Metadatas = BTreeMap<String, Metadata>;
+
+/// Samples of one metric for one set of labels, as scraped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Series {
+    /// `__name__` and labels, e.g. `source` for Netlify referrers
+    pub labels: Labels,
+    pub metadata: Metadata,
+    pub samples: BTreeMap<DateTime<Utc>, f64>,
+}
+
+impl Series {
+    /// Series of `data`, keyed by timestamps in milliseconds.
+    pub fn try_new<K, V>(
+        labels: Labels,
+        metadata: &Metadata,
+        data: &BTreeMap<K, V>,
+    ) -> Result<Series>
+    where
+        K: ToPrimitive + Copy + Debug,
+        V: ToPrimitive + Copy + Debug,
+    {
+        let samples = data
+            .iter()
+            .map(|(x, y)| {
+                let x = x
+                    .to_i64()
+                    .and_then(DateTime::<Utc>::from_timestamp_millis)
+                    .ok_or(anyhow!("Failed casting {:?} to a timestamp", x))?;
+                let y = y.to_f64().ok_or(anyhow!("Failed casting {:?} to f64", y))?;
+                Ok((x, y))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Series {
+            labels,
+            metadata: metadata.clone(),
+            samples,
+        })
+    }
+
+    /// Samples keyed by timestamps in milliseconds, as the analyses take
+    /// them.
+    pub fn millis(&self) -> BTreeMap<u64, f64> {
+        self.samples
+            .iter()
+            .map(|(x, y)| (x.timestamp_millis() as u64, *y))
+            .collect()
+    }
+
+    /// Line of the series labeled `label`, with its missing samples and their
+    /// timestamps as by [`gaps::line`].
+    pub fn line(
+        &self,
+        label: impl Into<String>,
+        interpolation: Option<Interpolation>,
+    ) -> Result<(Line, Vec<u64>)> {
+        gaps::line(label, &self.millis(), interpolation)
+    }
+
+    /// Series of a metric read back, e.g. from an earlier export, described by
+    /// `metadata` if it has the metric. Otherwise only its resolution is
+    /// known.
+    pub fn from_victoriametric(
+        victoriametric: &VictoriaMetric,
+        metadata: &Metadatas,
+    ) -> Result<Series> {
+        victoriametric.validate()?;
+        let labels = victoriametric.labels().clone();
+        let metadata = metadata
+            .get(labels.name())
+            .cloned()
+            .unwrap_or_else(|| Metadata {
+                unit: String::new(),
+                description: String::new(),
+                resolution: Resolution::of(&victoriametric.timestamps),
+            });
+        Series::try_new(
+            labels,
+            &metadata,
+            &victoriametric
+                .timestamps
+                .iter()
+                .copied()
+                .zip(victoriametric.values.iter().copied())
+                .collect(),
+        )
+    }
+
+    /// The series as a metric, its labels and `__name__`.
+    pub fn victoriametric(&self) -> VictoriaMetric {
+        let (timestamps, values) = self
+            .samples
+            .iter()
+            .map(|(x, y)| (x.timestamp_millis() as u64, *y))
+            .unzip();
+        VictoriaMetric {
+            metric: self.labels.clone(),
+            values,
+            timestamps,
+        }
+    }
+}
+
+/// Reads back the series of VictoriaMetrics JSON line files, as by
+/// [`read_victoriametrics_paths`], merging records of the same series.
+/// Metrics are described by `metadata` where it has them.
+pub fn read(paths: &[PathBuf], metadata: &Metadatas) -> Result<Vec<Series>> {
+    merge(read_victoriametrics_paths(paths)?)?
+        .iter()
+        .map(|victoriametric| Series::from_victoriametric(victoriametric, metadata))
+        .collect()
+}
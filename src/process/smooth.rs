@@ -0,0 +1,282 @@
+use crate::process::{resample::resolution, Line};
+use anyhow::{anyhow, bail, Error, Result};
+use num_traits::ToPrimitive;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::str::FromStr;
+
+/// Where in its window a smoothed point is placed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alignment {
+    /// At the last sample of the window, so the point only depends on the
+    /// past, e.g. the 7 day average of a day covers it and the 6 days before
+    Trailing,
+    /// At the middle sample of the window, so the smoothed line does not lag
+    /// behind. An even window has one more sample before than after it
+    Centred,
+}
+
+/// How a series is smoothed.
+///
+/// Windows count samples at the resolution of the series, days for Netlify
+/// and months for Google Trends. A point is only produced for a full window:
+/// when a sample is missing, the windows containing the gap are left out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Method {
+    Mean {
+        window: usize,
+        alignment: Alignment,
+    },
+    Median {
+        window: usize,
+        alignment: Alignment,
+    },
+    /// Exponentially weighted moving average, `s = alpha * x + (1 - alpha) * s`,
+    /// starting at the first sample. Every point is trailing.
+    Ewma {
+        alpha: f64,
+    },
+    /// Holt's double exponential smoothing, which also follows a trend
+    /// (smoothed by `beta`) instead of lagging behind it. Every point is
+    /// trailing.
+    Holt {
+        alpha: f64,
+        beta: f64,
+    },
+}
+
+/// Smoothing of the series of a graph, written as `GRAPH=METHOD`, e.g.
+/// `pageviews=mean:7`, `pageviews=median:28:centred`, `gtrends=ewma:0.3` or
+/// `visitors=holt:0.5:0.1`.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "String")]
+pub struct Smoothing {
+    pub graph: String,
+    pub method: Method,
+}
+
+fn parse_factor(name: &str, value: &str) -> Result<f64> {
+    let factor: f64 = value
+        .parse()
+        .map_err(|e| anyhow!("invalid {} {}: {}", name, value, e))?;
+    if !(factor > 0.0 && factor <= 1.0) {
+        bail!("{} must be in (0, 1], but is {}", name, factor);
+    }
+    Ok(factor)
+}
+
+impl FromStr for Method {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Method> {
+        let parts: Vec<_> = s.split(':').collect();
+        let window = |window: &str| -> Result<usize> {
+            match window.parse() {
+                Ok(0) | Err(_) => bail!("invalid window {}, expected a number of samples", window),
+                Ok(window) => Ok(window),
+            }
+        };
+        let alignment = |alignment: Option<&&str>| match alignment {
+            None | Some(&"trailing") => Ok(Alignment::Trailing),
+            Some(&"centred") | Some(&"centered") => Ok(Alignment::Centred),
+            Some(other) => Err(anyhow!(
+                "invalid alignment {}, expected trailing or centred",
+                other
+            )),
+        };
+        Ok(match parts.as_slice() {
+            ["mean", n] | ["mean", n, _] => Method::Mean {
+                window: window(n)?,
+                alignment: alignment(parts.get(2))?,
+            },
+            ["median", n] | ["median", n, _] => Method::Median {
+                window: window(n)?,
+                alignment: alignment(parts.get(2))?,
+            },
+            ["ewma", alpha] => Method::Ewma {
+                alpha: parse_factor("alpha", alpha)?,
+            },
+            ["holt", alpha, beta] => Method::Holt {
+                alpha: parse_factor("alpha", alpha)?,
+                beta: parse_factor("beta", beta)?,
+            },
+            _ => bail!(
+                "invalid smoothing {}, expected mean:N[:ALIGNMENT], median:N[:ALIGNMENT], ewma:ALPHA or holt:ALPHA:BETA",
+                s
+            ),
+        })
+    }
+}
+
+impl FromStr for Smoothing {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Smoothing> {
+        let (graph, method) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow!("invalid smoothing {}, expected GRAPH=METHOD", s))?;
+        Ok(Smoothing {
+            graph: graph.to_owned(),
+            method: method.parse()?,
+        })
+    }
+}
+
+impl TryFrom<String> for Smoothing {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Smoothing> {
+        s.parse()
+    }
+}
+
+impl Method {
+    /// Label of the smoothed line, `unit` being the resolution of the series,
+    /// e.g. `7 day avg`.
+    pub fn label(&self, unit: &str) -> String {
+        let (label, alignment) = match self {
+            Method::Mean { window, alignment } => (format!("{} {} avg", window, unit), alignment),
+            Method::Median { window, alignment } => {
+                (format!("{} {} median", window, unit), alignment)
+            }
+            Method::Ewma { alpha } => return format!("EWMA α={}", alpha),
+            Method::Holt { alpha, beta } => return format!("Holt α={} β={}", alpha, beta),
+        };
+        match alignment {
+            Alignment::Trailing => label,
+            Alignment::Centred => format!("{} (centred)", label),
+        }
+    }
+
+    /// Smooths `series`, timestamps in milliseconds.
+    pub fn smooth<K, V>(&self, series: &BTreeMap<K, V>) -> Result<BTreeMap<u64, f64>>
+    where
+        K: ToPrimitive + Copy + Debug,
+        V: ToPrimitive + Copy + Debug,
+    {
+        let samples = series
+            .iter()
+            .map(|(x, y)| {
+                let x = x.to_u64().ok_or(anyhow!("Failed casting {:?} to u64", x))?;
+                let y = y.to_f64().ok_or(anyhow!("Failed casting {:?} to f64", y))?;
+                Ok((x, y))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(match *self {
+            Method::Mean { window, alignment } => windowed(&samples, window, alignment, |values| {
+                values.iter().map(|value| value / values.len() as f64).sum()
+            }),
+            Method::Median { window, alignment } => {
+                windowed(&samples, window, alignment, |values| {
+                    let mut values = values.to_vec();
+                    values.sort_by(f64::total_cmp);
+                    let middle = values.len() / 2;
+                    if values.len() % 2 == 0 {
+                        (values[middle - 1] + values[middle]) / 2.0
+                    } else {
+                        values[middle]
+                    }
+                })
+            }
+            Method::Ewma { alpha } => {
+                let mut smoothed = BTreeMap::new();
+                let mut s = None;
+                for &(x, y) in &samples {
+                    let next = s.map_or(y, |s| alpha * y + (1.0 - alpha) * s);
+                    smoothed.insert(x, next);
+                    s = Some(next);
+                }
+                smoothed
+            }
+            Method::Holt { alpha, beta } => {
+                let mut smoothed = BTreeMap::new();
+                let (mut level, mut trend) = match samples.as_slice() {
+                    [(_, first), (_, second), ..] => (*first, second - first),
+                    _ => return Ok(samples.into_iter().collect()),
+                };
+                smoothed.insert(samples[0].0, level);
+                for &(x, y) in &samples[1..] {
+                    let previous = level;
+                    level = alpha * y + (1.0 - alpha) * (level + trend);
+                    trend = beta * (level - previous) + (1.0 - beta) * trend;
+                    smoothed.insert(x, level);
+                }
+                smoothed
+            }
+        })
+    }
+}
+
+/// Applies `f` to every full window of `window` consecutive samples. A
+/// window is full when its samples span no more than `window` steps of the
+/// series' resolution, half a step of slack allowing for months of
+/// different length.
+fn windowed(
+    samples: &[(u64, f64)],
+    window: usize,
+    alignment: Alignment,
+    f: impl Fn(&[f64]) -> f64,
+) -> BTreeMap<u64, f64> {
+    let timestamps: Vec<_> = samples.iter().map(|(x, _)| *x).collect();
+    let Some(step) = resolution(&timestamps) else {
+        return BTreeMap::new();
+    };
+    let max_span = (window as u64 - 1) * step + step / 2;
+
+    samples
+        .windows(window)
+        .filter(|samples| samples[window - 1].0 - samples[0].0 <= max_span)
+        .map(|samples| {
+            let x = match alignment {
+                Alignment::Trailing => samples[window - 1].0,
+                Alignment::Centred => samples[window / 2].0,
+            };
+            let values: Vec<_> = samples.iter().map(|(_, y)| *y).collect();
+            (x, f(&values))
+        })
+        .collect()
+}
+
+/// Smoothed lines for the `smoothings` of `graph`, whose series are
+/// `series` by label. With a single series a line is labeled by its
+/// smoothing alone, e.g. `7 day avg`, otherwise by series and smoothing.
+pub fn lines<K, V>(
+    graph: &str,
+    series: &[(String, &BTreeMap<K, V>)],
+    smoothings: &[Smoothing],
+    unit: &str,
+) -> Result<Vec<Line>>
+where
+    K: ToPrimitive + Copy + Debug,
+    V: ToPrimitive + Copy + Debug,
+{
+    let mut lines = vec![];
+    for smoothing in smoothings.iter().filter(|s| s.graph == graph) {
+        for (label, data) in series {
+            let smoothed = smoothing.method.smooth(data)?;
+            let label = match series.len() {
+                1 => smoothing.method.label(unit),
+                _ => format!("{} {}", label, smoothing.method.label(unit)),
+            };
+            lines.push(Line::try_new(label, &smoothed)?);
+        }
+    }
+    Ok(lines)
+}
+
+/// Fails for smoothings of graphs not in `graphs`, which would silently do
+/// nothing otherwise.
+pub fn check(smoothings: &[Smoothing], graphs: &[&str]) -> Result<()> {
+    for smoothing in smoothings {
+        if !graphs.contains(&smoothing.graph.as_str()) {
+            bail!(
+                "Graph {} cannot be smoothed, only {}",
+                smoothing.graph,
+                graphs.join(", ")
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,334 @@
+use crate::{
+    gtrends, netlify,
+    process::{combine, reconcile::Report, Options, Processed},
+};
+use anyhow::{anyhow, bail, Result};
+use chrono::Utc;
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub mod export;
+pub mod ingest;
+
+/// Database used when neither `--db` nor `store.db` is given.
+pub const DEFAULT_DB: &str = "nixos-metrics.sqlite";
+
+const SCHEMA: &str = "
+-- every distinct version of a scraped file, as it was read and
+-- decompressed, with the SHA-256 of that content; Netlify snapshots in
+-- archives are files of their own, e.g. 2023.tar.gz/2023-033.json
+CREATE TABLE IF NOT EXISTS snapshots (
+    id INTEGER PRIMARY KEY,
+    source TEXT NOT NULL,
+    file TEXT NOT NULL,
+    content TEXT NOT NULL,
+    hash TEXT NOT NULL,
+    ingested_at TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS snapshots_file ON snapshots (source, file);
+
+-- data points as scraped, before any processing, but for the reports below;
+-- labels is a JSON object without __name__, e.g. {\"source\":\"github.com\"}
+-- or {} when there are none
+CREATE TABLE IF NOT EXISTS samples (
+    metric TEXT NOT NULL,
+    labels TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    value REAL NOT NULL,
+    source_file TEXT NOT NULL,
+    PRIMARY KEY (metric, labels, timestamp)
+);
+
+-- what every snapshot said about a day's Netlify pageviews and visitors,
+-- reconciled when processing; taken is the day of the snapshot
+CREATE TABLE IF NOT EXISTS reports (
+    metric TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    value REAL NOT NULL,
+    source_file TEXT NOT NULL,
+    taken INTEGER NOT NULL,
+    PRIMARY KEY (metric, timestamp, source_file)
+);
+
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY,
+    command TEXT NOT NULL,
+    started_at TEXT NOT NULL,
+    finished_at TEXT,
+    snapshots INTEGER,
+    samples INTEGER,
+    error TEXT
+);
+";
+
+/// What a run did, recorded in the `runs` table.
+#[derive(Debug, Default)]
+pub struct Counts {
+    pub snapshots: usize,
+    pub samples: usize,
+}
+
+/// SQLite database with all scraped snapshots and their samples.
+pub struct Store {
+    path: PathBuf,
+    conn: Connection,
+}
+
+impl Store {
+    /// Opens the database at `path`, creating it if needed.
+    pub fn open(path: &Path) -> Result<Store> {
+        let conn = Connection::open(path)
+            .map_err(|e| anyhow!("Unable to open database {}: {}", path.display(), e))?;
+        conn.execute_batch(SCHEMA)
+            .map_err(|e| anyhow!("Unable to create tables in {}: {}", path.display(), e))?;
+        Ok(Store {
+            path: path.to_owned(),
+            conn,
+        })
+    }
+
+    /// Records the start of a run of `command` in `runs`, returning its id.
+    pub fn start_run(&self, command: &str) -> Result<i64> {
+        self.conn.execute(
+            "INSERT INTO runs (command, started_at) VALUES (?1, ?2)",
+            params![command, Utc::now().to_rfc3339()],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// Records how the run `id` went.
+    pub fn finish_run(&self, id: i64, result: &Result<Counts>) -> Result<()> {
+        let (counts, error) = match result {
+            Ok(counts) => (Some(counts), None),
+            Err(e) => (None, Some(format!("{:#}", e))),
+        };
+        self.conn.execute(
+            "UPDATE runs SET finished_at = ?2, snapshots = ?3, samples = ?4, error = ?5
+             WHERE id = ?1",
+            params![
+                id,
+                Utc::now().to_rfc3339(),
+                counts.map(|counts| counts.snapshots),
+                counts.map(|counts| counts.samples),
+                error
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Stores `file_content` as the latest snapshot of `file`, unless it
+    /// already is, which is told by its hash. Returns whether it was new.
+    fn add_snapshot(
+        tx: &Transaction,
+        source: &str,
+        file: &str,
+        file_content: &str,
+    ) -> Result<bool> {
+        let hash = netlify::ingest::hash(file_content.as_bytes());
+        let latest: Option<String> = tx
+            .query_row(
+                "SELECT hash FROM snapshots WHERE source = ?1 AND file = ?2
+                 ORDER BY id DESC LIMIT 1",
+                params![source, file],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if latest.as_deref() == Some(hash.as_str()) {
+            return Ok(false);
+        }
+        tx.execute(
+            "INSERT INTO snapshots (source, file, content, hash, ingested_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![source, file, file_content, hash, Utc::now().to_rfc3339()],
+        )?;
+        Ok(true)
+    }
+
+    /// Ingests every Netlify snapshot in `dir` that changed since the last
+    /// ingest, compressed and archived ones too, see
+    /// [`netlify::ingest::contents`]. Only those are parsed. What each one
+    /// says about a day's pageviews and visitors is kept in `reports`, where
+    /// snapshots disagree too, to be reconciled when processing.
+    pub fn ingest_netlify(&mut self, dir: &Path) -> Result<Counts> {
+        let tx = self.conn.transaction()?;
+        let mut counts = Counts::default();
+        let contents = netlify::ingest::contents(&netlify::ingest::paths(dir)?)?;
+
+        for (path, file_content) in contents {
+            let file = path
+                .strip_prefix(dir)
+                .unwrap_or(&path)
+                .display()
+                .to_string();
+            let file_content = String::from_utf8(file_content)
+                .map_err(|e| anyhow!("Unable to read file {}: {}", path.display(), e))?;
+            if !Self::add_snapshot(&tx, "netlify", &file, &file_content)? {
+                continue;
+            }
+            let snapshot = netlify::process::Snapshot::read(&path, file_content.as_bytes())?;
+            counts.snapshots += 1;
+
+            for (metric, data) in [
+                ("netlify.pageviews", &snapshot.pageviews),
+                ("netlify.visitors", &snapshot.visitors),
+            ] {
+                for (&tstamp, &datum) in data {
+                    let existing: Option<f64> = tx
+                        .query_row(
+                            "SELECT value FROM reports
+                             WHERE metric = ?1 AND timestamp = ?2 AND source_file = ?3",
+                            params![metric, tstamp, file],
+                            |row| row.get(0),
+                        )
+                        .optional()?;
+                    if existing == Some(datum as f64) {
+                        continue;
+                    }
+                    tx.execute(
+                        "INSERT OR REPLACE INTO reports (metric, timestamp, value, source_file, taken)
+                         VALUES (?1, ?2, ?3, ?4, ?5)",
+                        params![metric, tstamp, datum as f64, file, snapshot.date],
+                    )?;
+                    counts.samples += 1;
+                }
+            }
+
+            // snapshots of the same day replace each other, as when processing
+            for (source, &count) in &snapshot.sources {
+                tx.execute(
+                    "INSERT OR REPLACE INTO samples (metric, labels, timestamp, value, source_file)
+                     VALUES ('netlify.sources', ?1, ?2, ?3, ?4)",
+                    params![
+                        serde_json::to_string(&BTreeMap::from([("source", source)]))?,
+                        snapshot.date,
+                        count as f64,
+                        file
+                    ],
+                )?;
+                counts.samples += 1;
+            }
+        }
+
+        tx.commit()?;
+        Ok(counts)
+    }
+
+    /// Ingests the Google Trends data in `path` if it changed since the last
+    /// ingest. Google scales the search interest to the whole time range on
+    /// every scrape, so new data replaces all earlier samples.
+    pub fn ingest_gtrends(&mut self, path: &Path) -> Result<Counts> {
+        let tx = self.conn.transaction()?;
+        let mut counts = Counts::default();
+        let file = file_name(path)?;
+        let file_content = fs::read_to_string(path)
+            .map_err(|e| anyhow!("Unable to read file {}: {}", path.display(), e))?;
+
+        if Self::add_snapshot(&tx, "gtrends", &file, &file_content)? {
+            let data = gtrends::process::Data::parse(path, &file_content)?;
+            counts.snapshots += 1;
+            tx.execute("DELETE FROM samples WHERE metric = 'gtrends'", [])?;
+            for (search_term, gtrend) in &data.gtrends {
+                let labels =
+                    serde_json::to_string(&BTreeMap::from([("search_term", search_term)]))?;
+                for (&tstamp, &value) in gtrend {
+                    tx.execute(
+                        "INSERT INTO samples (metric, labels, timestamp, value, source_file)
+                         VALUES ('gtrends', ?1, ?2, ?3, ?4)",
+                        params![labels, tstamp, value, file],
+                    )?;
+                    counts.samples += 1;
+                }
+            }
+        }
+
+        tx.commit()?;
+        Ok(counts)
+    }
+
+    /// Processes all stored samples, just like processing the scraped files
+    /// would, reconciling the reports of pageviews and visitors by the
+    /// reconciliation of `netlify_options`.
+    pub fn process(
+        &self,
+        netlify_options: &Options,
+        gtrends_options: &Options,
+    ) -> Result<(Processed, Counts)> {
+        let mut netlify = netlify::process::Data::new(netlify_options.reconciliation);
+        let mut gtrends = gtrends::process::Data::default();
+        let mut counts = Counts::default();
+
+        // in the order the snapshots were taken, as when processing them
+        let mut statement = self.conn.prepare(
+            "SELECT metric, timestamp, value, source_file, taken FROM reports
+             ORDER BY metric, timestamp, taken, source_file",
+        )?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let metric: String = row.get(0)?;
+            let report = Report {
+                file: PathBuf::from(row.get::<_, String>(3)?),
+                taken: row.get(4)?,
+                value: row.get(2)?,
+            };
+            netlify.report(&metric, row.get(1)?, report)?;
+            counts.samples += 1;
+        }
+
+        let mut statement = self
+            .conn
+            .prepare("SELECT metric, labels, timestamp, value FROM samples")?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let metric: String = row.get(0)?;
+            let labels: BTreeMap<String, String> = serde_json::from_str(&row.get::<_, String>(1)?)?;
+            let tstamp: u64 = row.get(2)?;
+            let value: f64 = row.get(3)?;
+            let label = |name: &str| {
+                labels
+                    .get(name)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("sample of {} without {} label", metric, name))
+            };
+            match metric.as_str() {
+                "netlify.sources" => {
+                    netlify
+                        .sources
+                        .entry(label("source")?)
+                        .or_default()
+                        .insert(tstamp, value as u64);
+                }
+                "gtrends" => {
+                    gtrends
+                        .gtrends
+                        .entry(label("search_term")?)
+                        .or_default()
+                        .insert(tstamp, value);
+                }
+                _ => bail!("Unknown metric {} in {}", metric, self.path.display()),
+            }
+            counts.samples += 1;
+        }
+
+        if counts.samples == 0 {
+            bail!("No samples in {}, run ingest first", self.path.display());
+        }
+        let mut sources = vec![];
+        if !netlify.pageviews.is_empty() {
+            sources.push(netlify.process(netlify_options)?);
+        }
+        if !gtrends.gtrends.is_empty() {
+            sources.push(gtrends.process(gtrends_options)?);
+        }
+        Ok((combine(sources)?, counts))
+    }
+}
+
+fn file_name(path: &Path) -> Result<String> {
+    Ok(path
+        .file_name()
+        .ok_or_else(|| anyhow!("{} is not a file", path.display()))?
+        .to_string_lossy()
+        .into_owned())
+}
@@ -0,0 +1,50 @@
+use crate::{
+    config::Config,
+    output::Outputs,
+    process::Options,
+    store::{Store, DEFAULT_DB},
+};
+use anyhow::Result;
+use clap::Parser;
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Debug, Clone, Default)]
+#[command(version, about, author, long_about = None)]
+pub struct Cli {
+    /// SQLite database to export from [default: nixos-metrics.sqlite]
+    #[clap(long, value_parser = clap::value_parser!(PathBuf))]
+    db: Option<PathBuf>,
+
+    #[clap(skip)]
+    netlify_options: Options,
+
+    #[clap(skip)]
+    gtrends_options: Options,
+
+    #[command(flatten)]
+    outputs: Outputs,
+}
+
+impl Cli {
+    /// Fills every argument not given on the command line from `config`.
+    pub fn with_config(&self, config: &Config) -> Cli {
+        Cli {
+            db: self.db.clone().or(config.store.db.clone()),
+            netlify_options: config.sources.netlify.options(),
+            gtrends_options: config.sources.gtrends.options(),
+            outputs: self.outputs.with_defaults(&config.outputs),
+        }
+    }
+}
+
+pub async fn run(args: &Cli) -> Result<()> {
+    let store = Store::open(args.db.as_deref().unwrap_or(Path::new(DEFAULT_DB)))?;
+
+    let id = store.start_run("export")?;
+    let result = match store.process(&args.netlify_options, &args.gtrends_options) {
+        Ok((processed, counts)) => args.outputs.write(&processed).await.map(|()| counts),
+        Err(e) => Err(e),
+    };
+    store.finish_run(id, &result)?;
+    result.map(|_| ())
+}
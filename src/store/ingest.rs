@@ -0,0 +1,76 @@
+use crate::{
+    config::Config,
+    store::{Counts, Store, DEFAULT_DB},
+};
+use anyhow::{bail, Result};
+use clap::Parser;
+use log::info;
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Debug, Clone, Default)]
+#[command(version, about, author, long_about = None)]
+pub struct Cli {
+    /// SQLite database to ingest into [default: nixos-metrics.sqlite]
+    #[clap(long, value_parser = clap::value_parser!(PathBuf))]
+    db: Option<PathBuf>,
+
+    /// Directory where the Netlify data has been collected
+    #[clap(long, value_parser = clap::value_parser!(PathBuf))]
+    netlify_dir: Option<PathBuf>,
+
+    /// File where the Google Trends data has been collected
+    #[clap(long, value_parser = clap::value_parser!(PathBuf))]
+    gtrends_data: Option<PathBuf>,
+}
+
+impl Cli {
+    /// Fills every argument not given on the command line from `config`.
+    pub fn with_config(&self, config: &Config) -> Cli {
+        Cli {
+            db: self.db.clone().or(config.store.db.clone()),
+            netlify_dir: self
+                .netlify_dir
+                .clone()
+                .or(config.sources.netlify.dir.clone()),
+            gtrends_data: self
+                .gtrends_data
+                .clone()
+                .or(config.sources.gtrends.data.clone()),
+        }
+    }
+}
+
+pub async fn run(args: &Cli) -> Result<()> {
+    if args.netlify_dir.is_none() && args.gtrends_data.is_none() {
+        bail!("Nothing to ingest, pass --netlify-dir or --gtrends-data");
+    }
+    let db = args.db.as_deref().unwrap_or(Path::new(DEFAULT_DB));
+    let mut store = Store::open(db)?;
+
+    let id = store.start_run("ingest")?;
+    let result = ingest(&mut store, args);
+    store.finish_run(id, &result)?;
+    let counts = result?;
+    info!(
+        "Ingested {} new snapshots with {} samples into {}",
+        counts.snapshots,
+        counts.samples,
+        db.display()
+    );
+    Ok(())
+}
+
+fn ingest(store: &mut Store, args: &Cli) -> Result<Counts> {
+    let mut counts = Counts::default();
+    if let Some(dir) = &args.netlify_dir {
+        let netlify = store.ingest_netlify(dir)?;
+        counts.snapshots += netlify.snapshots;
+        counts.samples += netlify.samples;
+    }
+    if let Some(data) = &args.gtrends_data {
+        let gtrends = store.ingest_gtrends(data)?;
+        counts.snapshots += gtrends.snapshots;
+        counts.samples += gtrends.samples;
+    }
+    Ok(counts)
+}